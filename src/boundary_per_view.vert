@@ -0,0 +1,14 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj[2];
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 line_color;
+
+void main() {
+    gl_Position = view_proj[0] * vec4(position, 1.0);
+    line_color = color;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    uint view_index;
+};
+
+layout(location = 0) in vec2 screen_coords;
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = vec4(screen_coords, view_index, 1);
+}
@@ -1,62 +1,87 @@
-use std::{
-    io::Cursor,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
-};
+use std::{ffi::CString, io::Cursor, sync::Arc};
 
 use ash::{
     util::read_spv,
     vk::{self, Handle},
 };
-use ash::vk::RenderPass;
 use openxr as xr;
-use openxr::{vulkan, Session, Vulkan};
+use openxr::{Session, Vulkan};
 use openxr_sys::EnvironmentBlendMode;
-use crate::kstructs::{Swapchain, Framebuffer};
-use crate::kconstants::{COLOR_FORMAT, VIEW_COUNT, VIEW_TYPE, PIPELINE_DEPTH};
-
-#[derive(Debug, Clone, Copy)]
-struct Vertex {
-    pos: [f32; 3],
-    color: [f32; 3],
-}
+use crate::kstructs::{
+    Buffer, CommandRing, DepthSource, DeviceContext, Framebuffer, Image, Pipeline, RenderConfig,
+    RenderPass, Swapchain, ViewMode,
+};
+use crate::kboundary;
+use crate::kcontext::KaleidoContextBuilder;
+use crate::kerror::{KaleidoError, Result};
 
 pub fn init_vulkan(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
-    vk_target_version: u32,
-) -> (ash::Instance, vk::PhysicalDevice, ash::Device, vk::Queue, u32) {
+    settings: &KaleidoContextBuilder,
+) -> Result<Arc<DeviceContext>> {
+    let application_name = CString::new(settings.application_name.as_str()).unwrap_or_default();
+    let engine_name = CString::new(settings.engine_name.as_str()).unwrap_or_default();
     unsafe {
-        let vk_entry = ash::Entry::load().unwrap();
+        let vk_entry =
+            ash::Entry::load().map_err(|e| KaleidoError::Loader(e.to_string()))?;
         let vk_app_info = vk::ApplicationInfo::default()
-            .application_version(0)
-            .engine_version(0)
-            .api_version(vk_target_version);
+            .application_name(&application_name)
+            .application_version(settings.application_version)
+            .engine_name(&engine_name)
+            .engine_version(settings.engine_version)
+            .api_version(settings.vk_target_version());
 
         let vk_instance = {
             let vk_instance = xr_instance
                 .create_vulkan_instance(
                     system,
-                    std::mem::transmute(vk_entry.static_fn().get_instance_proc_addr),
+                    std::mem::transmute::<vk::PFN_vkGetInstanceProcAddr, xr::sys::platform::VkGetInstanceProcAddr>(
+                        vk_entry.static_fn().get_instance_proc_addr,
+                    ),
                     &vk::InstanceCreateInfo::default().application_info(&vk_app_info) as *const _
                         as *const _,
-                )
-                .expect("XR error creating Vulkan instance")
-                .map_err(vk::Result::from_raw)
-                .expect("Vulkan error creating Vulkan instance");
+                )?
+                .map_err(vk::Result::from_raw)?;
             ash::Instance::load(
                 vk_entry.static_fn(),
                 vk::Instance::from_raw(vk_instance as _),
             )
         };
 
+        // Nothing owns the instance until the device context is built, so clean it up by hand if
+        // anything in between fails.
+        match create_vulkan_device(xr_instance, system, &vk_entry, &vk_instance, &settings.render_config) {
+            Ok((vk_physical_device, vk_device, queue, queue_family_index)) => {
+                Ok(Arc::new(DeviceContext::new(
+                    vk_entry,
+                    vk_instance,
+                    vk_physical_device,
+                    vk_device,
+                    queue,
+                    queue_family_index,
+                )))
+            }
+            Err(e) => {
+                vk_instance.destroy_instance(None);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn create_vulkan_device(
+    xr_instance: &xr::Instance,
+    system: xr::SystemId,
+    vk_entry: &ash::Entry,
+    vk_instance: &ash::Instance,
+    config: &RenderConfig,
+) -> Result<(vk::PhysicalDevice, ash::Device, vk::Queue, u32)> {
+    unsafe {
         let vk_physical_device = vk::PhysicalDevice::from_raw(
             xr_instance
-                .vulkan_graphics_device(system, vk_instance.handle().as_raw() as _)
-                .unwrap() as _,
+                .vulkan_graphics_device(system, vk_instance.handle().as_raw() as _)?
+                as _,
         );
 
         let queue_family_index = vk_instance
@@ -70,98 +95,307 @@ pub fn init_vulkan(
                     None
                 }
             })
-            .expect("Vulkan device has no graphics queue");
+            .ok_or(KaleidoError::NoGraphicsQueue)?;
+
+        // Only ask for multiview when we render with it, so the per-view path also works on
+        // devices that don't offer it.
+        let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures {
+            multiview: vk::TRUE,
+            ..Default::default()
+        };
+        let queue_create_infos = [vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&[1.0])];
+        let mut device_create_info =
+            vk::DeviceCreateInfo::default().queue_create_infos(&queue_create_infos);
+        if config.view_mode == ViewMode::Multiview {
+            device_create_info = device_create_info.push_next(&mut multiview_features);
+        }
 
         let vk_device = {
             let vk_device = xr_instance
                 .create_vulkan_device(
                     system,
-                    std::mem::transmute(vk_entry.static_fn().get_instance_proc_addr),
+                    std::mem::transmute::<vk::PFN_vkGetInstanceProcAddr, xr::sys::platform::VkGetInstanceProcAddr>(
+                        vk_entry.static_fn().get_instance_proc_addr,
+                    ),
                     vk_physical_device.as_raw() as _,
-                    &vk::DeviceCreateInfo::default()
-                        .queue_create_infos(&[vk::DeviceQueueCreateInfo::default()
-                            .queue_family_index(queue_family_index)
-                            .queue_priorities(&[1.0])])
-                        .push_next(&mut vk::PhysicalDeviceMultiviewFeatures {
-                            multiview: vk::TRUE,
-                            ..Default::default()
-                        }) as *const _ as *const _,
-                )
-                .expect("XR error creating Vulkan device")
-                .map_err(vk::Result::from_raw)
-                .expect("Vulkan error creating Vulkan device");
+                    &device_create_info as *const _ as *const _,
+                )?
+                .map_err(vk::Result::from_raw)?;
 
             ash::Device::load(vk_instance.fp_v1_0(), vk::Device::from_raw(vk_device as _))
         };
 
         let queue = vk_device.get_device_queue(queue_family_index, 0);
 
-        (vk_instance, vk_physical_device, vk_device, queue, queue_family_index)
+        Ok((vk_physical_device, vk_device, queue, queue_family_index))
     }
 }
-pub fn create_render_pass(vk_device: &ash::Device) -> vk::RenderPass {
+
+pub fn create_render_pass(device: &Arc<DeviceContext>, config: &RenderConfig) -> Result<RenderPass> {
     unsafe {
-        let view_mask = !(!0 << VIEW_COUNT);
-        vk_device
-            .create_render_pass(
-                &vk::RenderPassCreateInfo::default()
-                    .attachments(&[vk::AttachmentDescription {
-                        format: COLOR_FORMAT,
-                        samples: vk::SampleCountFlags::TYPE_1,
-                        load_op: vk::AttachmentLoadOp::CLEAR,
-                        store_op: vk::AttachmentStoreOp::STORE,
-                        initial_layout: vk::ImageLayout::UNDEFINED,
-                        final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        ..Default::default()
-                    }])
-                    .subpasses(&[vk::SubpassDescription::default()
-                        .color_attachments(&[vk::AttachmentReference {
-                            attachment: 0,
-                            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        }])
-                        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)])
-                    .dependencies(&[vk::SubpassDependency {
-                        src_subpass: vk::SUBPASS_EXTERNAL,
-                        dst_subpass: 0,
-                        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-                        ..Default::default()
-                    }])
-                    .push_next(
-                        &mut vk::RenderPassMultiviewCreateInfo::default()
-                            .view_masks(&[view_mask])
-                            .correlation_masks(&[view_mask]),
-                    ),
-                None,
-            )
-            .unwrap()
+        let view_mask = !(!0 << config.view_count);
+        let view_masks = [view_mask];
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::default()
+            .view_masks(&view_masks)
+            .correlation_masks(&view_masks);
+
+        // With MSAA the color attachment is a transient multisampled image that only lives for
+        // the pass; the swapchain image becomes the resolve attachment after depth.
+        let mut attachments = vec![vk::AttachmentDescription {
+            format: config.color_format,
+            samples: config.sample_count(),
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: if config.multisampled() {
+                vk::AttachmentStoreOp::DONT_CARE
+            } else {
+                vk::AttachmentStoreOp::STORE
+            },
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        }];
+        let depth_reference = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        if let Some(source) = config.depth {
+            attachments.push(vk::AttachmentDescription {
+                format: config.depth_format,
+                samples: config.sample_count(),
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                // Only depth handed to the runtime needs to survive the pass.
+                store_op: if source == DepthSource::XrSwapchain {
+                    vk::AttachmentStoreOp::STORE
+                } else {
+                    vk::AttachmentStoreOp::DONT_CARE
+                },
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            });
+        }
+
+        let resolve_references = [vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        if config.multisampled() {
+            attachments.push(vk::AttachmentDescription {
+                format: config.color_format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::STORE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            });
+        }
+
+        let color_references = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let mut subpass = vk::SubpassDescription::default()
+            .color_attachments(&color_references)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if config.depth.is_some() {
+            subpass = subpass.depth_stencil_attachment(&depth_reference);
+        }
+        if config.multisampled() {
+            subpass = subpass.resolve_attachments(&resolve_references);
+        }
+
+        let subpasses = [subpass];
+        let dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+        let mut create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        // Without multiview the pass renders a single layer and is begun once per view.
+        if config.view_mode == ViewMode::Multiview {
+            create_info = create_info.push_next(&mut multiview_info);
+        }
+
+        let render_pass = device.device.create_render_pass(&create_info, None)?;
+        Ok(RenderPass::new(device.clone(), render_pass))
     }
 }
 
+/// Builds the fullscreen debug pipeline. Depth testing is switched on when `config` renders with a
+/// depth attachment.
+///
+/// Without multiview the fragment shader can't use `gl_ViewIndex`, so the per-view variant reads
+/// the view index from a `u32` fragment push constant at offset 0 instead.
 pub fn create_pipeline(
-    vk_device: &ash::Device,
-    render_pass: vk::RenderPass,
-) -> (vk::Pipeline, vk::PipelineLayout) {
+    device: &Arc<DeviceContext>,
+    render_pass: &RenderPass,
+    config: &RenderConfig,
+) -> Result<Pipeline> {
+    let frag: &[u8] = match config.view_mode {
+        ViewMode::Multiview => include_bytes!("debug_pattern.frag.spv"),
+        ViewMode::PerView => include_bytes!("debug_pattern_per_view.frag.spv"),
+    };
+    let push_constant_ranges = match config.view_mode {
+        ViewMode::Multiview => &[][..],
+        ViewMode::PerView => &[vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<u32>() as u32,
+        }][..],
+    };
+    build_pipeline(
+        device,
+        render_pass,
+        config,
+        &PipelineDesc {
+            vert: include_bytes!("fullscreen.vert.spv"),
+            frag,
+            push_constant_ranges,
+            vertex_input: vk::PipelineVertexInputStateCreateInfo::default(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            blend: vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ZERO,
+                color_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B,
+                ..Default::default()
+            },
+            depth_write: true,
+        },
+    )
+}
+
+/// Builds the pipeline `BoundaryGrid` draws with: alpha-blended line lists of position and color
+/// vertices, transformed by one view-projection matrix per view from a vertex push constant.
+/// Lines are depth tested against the scene when `config` renders with depth, but don't write it.
+pub fn create_boundary_pipeline(
+    device: &Arc<DeviceContext>,
+    render_pass: &RenderPass,
+    config: &RenderConfig,
+) -> Result<Pipeline> {
+    let vert: &[u8] = match config.view_mode {
+        ViewMode::Multiview => include_bytes!("boundary.vert.spv"),
+        ViewMode::PerView => include_bytes!("boundary_per_view.vert.spv"),
+    };
+    let bindings = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: kboundary::VERTEX_SIZE,
+        input_rate: vk::VertexInputRate::VERTEX,
+    }];
+    let attributes = [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 0,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: 12,
+        },
+    ];
+    build_pipeline(
+        device,
+        render_pass,
+        config,
+        &PipelineDesc {
+            vert,
+            frag: include_bytes!("boundary.frag.spv"),
+            // One mat4 per view; the per-view shader only reads the first.
+            push_constant_ranges: &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: kboundary::PUSH_CONSTANTS_SIZE,
+            }],
+            vertex_input: vk::PipelineVertexInputStateCreateInfo::default()
+                .vertex_binding_descriptions(&bindings)
+                .vertex_attribute_descriptions(&attributes),
+            topology: vk::PrimitiveTopology::LINE_LIST,
+            blend: vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B,
+                ..Default::default()
+            },
+            depth_write: false,
+        },
+    )
+}
+
+/// What sets one of kaleido's pipelines apart from the others.
+struct PipelineDesc<'a> {
+    /// SPIR-V for the vertex and fragment stages, both entered at `main`.
+    vert: &'a [u8],
+    frag: &'a [u8],
+    push_constant_ranges: &'a [vk::PushConstantRange],
+    vertex_input: vk::PipelineVertexInputStateCreateInfo<'a>,
+    topology: vk::PrimitiveTopology,
+    blend: vk::PipelineColorBlendAttachmentState,
+    /// Depth is only tested, and written if this is set, when `config` renders with depth.
+    depth_write: bool,
+}
+
+/// Builds a single-subpass pipeline for `render_pass` from `desc`, with dynamic viewport and
+/// scissor, no culling and `config`'s sample count.
+fn build_pipeline(
+    device: &Arc<DeviceContext>,
+    render_pass: &RenderPass,
+    config: &RenderConfig,
+    desc: &PipelineDesc,
+) -> Result<Pipeline> {
+    let vk_device = &device.device;
     unsafe {
-        let vert = read_spv(&mut Cursor::new(&include_bytes!("fullscreen.vert.spv")[..])).unwrap();
-        let frag = read_spv(&mut Cursor::new(
-            &include_bytes!("debug_pattern.frag.spv")[..],
-        ))
-            .unwrap();
+        let vert = read_spv(&mut Cursor::new(desc.vert)).map_err(KaleidoError::InvalidShader)?;
+        let frag = read_spv(&mut Cursor::new(desc.frag)).map_err(KaleidoError::InvalidShader)?;
         let vert = vk_device
-            .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&vert), None)
-            .unwrap();
-        let frag = vk_device
+            .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&vert), None)?;
+        let frag = match vk_device
             .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&frag), None)
-            .unwrap();
+        {
+            Ok(frag) => frag,
+            Err(e) => {
+                vk_device.destroy_shader_module(vert, None);
+                return Err(e.into());
+            }
+        };
 
-        let pipeline_layout = vk_device
-            .create_pipeline_layout(
-                &vk::PipelineLayoutCreateInfo::default().set_layouts(&[]),
-                None,
-            )
-            .unwrap();
+        let pipeline_layout = vk_device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&[])
+                .push_constant_ranges(desc.push_constant_ranges),
+            None,
+        );
+        let pipeline_layout = match pipeline_layout {
+            Ok(layout) => layout,
+            Err(e) => {
+                vk_device.destroy_shader_module(vert, None);
+                vk_device.destroy_shader_module(frag, None);
+                return Err(e.into());
+            }
+        };
 
         let noop_stencil_state = vk::StencilOpState {
             fail_op: vk::StencilOp::KEEP,
@@ -180,20 +414,20 @@ pub fn create_pipeline(
                         vk::PipelineShaderStageCreateInfo {
                             stage: vk::ShaderStageFlags::VERTEX,
                             module: vert,
-                            p_name: b"main\0".as_ptr() as _,
+                            p_name: c"main".as_ptr(),
                             ..Default::default()
                         },
                         vk::PipelineShaderStageCreateInfo {
                             stage: vk::ShaderStageFlags::FRAGMENT,
                             module: frag,
-                            p_name: b"main\0".as_ptr() as _,
+                            p_name: c"main".as_ptr(),
                             ..Default::default()
                         },
                     ])
-                    .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                    .vertex_input_state(&desc.vertex_input)
                     .input_assembly_state(
                         &vk::PipelineInputAssemblyStateCreateInfo::default()
-                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                            .topology(desc.topology),
                     )
                     .viewport_state(
                         &vk::PipelineViewportStateCreateInfo::default()
@@ -208,28 +442,19 @@ pub fn create_pipeline(
                     )
                     .multisample_state(
                         &vk::PipelineMultisampleStateCreateInfo::default()
-                            .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                            .rasterization_samples(config.sample_count()),
                     )
                     .depth_stencil_state(
                         &vk::PipelineDepthStencilStateCreateInfo::default()
-                            .depth_test_enable(false)
-                            .depth_write_enable(false)
+                            .depth_test_enable(config.depth.is_some())
+                            .depth_write_enable(config.depth.is_some() && desc.depth_write)
+                            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
                             .front(noop_stencil_state)
                             .back(noop_stencil_state),
                     )
                     .color_blend_state(
-                        &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
-                            vk::PipelineColorBlendAttachmentState {
-                                blend_enable: vk::TRUE,
-                                src_color_blend_factor: vk::BlendFactor::ONE,
-                                dst_color_blend_factor: vk::BlendFactor::ZERO,
-                                color_blend_op: vk::BlendOp::ADD,
-                                color_write_mask: vk::ColorComponentFlags::R
-                                    | vk::ColorComponentFlags::G
-                                    | vk::ColorComponentFlags::B,
-                                ..Default::default()
-                            },
-                        ]),
+                        &vk::PipelineColorBlendStateCreateInfo::default()
+                            .attachments(std::slice::from_ref(&desc.blend)),
                     )
                     .dynamic_state(
                         &vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&[
@@ -238,27 +463,41 @@ pub fn create_pipeline(
                         ]),
                     )
                     .layout(pipeline_layout)
-                    .render_pass(render_pass)
+                    .render_pass(render_pass.handle)
                     .subpass(0)],
                 None,
             )
-            .unwrap()[0];
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, e)| e);
 
+        // The modules are only needed while the pipeline is being built, so release them
+        // whether or not that worked.
         vk_device.destroy_shader_module(vert, None);
         vk_device.destroy_shader_module(frag, None);
 
-        (pipeline, pipeline_layout)
+        match pipeline {
+            Ok(pipeline) => Ok(Pipeline::new(device.clone(), pipeline, pipeline_layout)),
+            Err(e) => {
+                vk_device.destroy_pipeline_layout(pipeline_layout, None);
+                Err(e.into())
+            }
+        }
     }
 }
 
-
-pub fn init_openxr() -> (xr::Instance, xr::SystemId, EnvironmentBlendMode) {
+pub fn init_openxr(
+    settings: &KaleidoContextBuilder,
+) -> Result<(xr::Instance, xr::SystemId, EnvironmentBlendMode)> {
     #[cfg(feature = "static")]
     let entry = xr::Entry::linked();
     #[cfg(not(feature = "static"))]
     let entry = unsafe {
-        xr::Entry::load()
-            .expect("couldn't find the OpenXR loader; try enabling the \"static\" feature")
+        xr::Entry::load().map_err(|e| {
+            KaleidoError::Loader(format!(
+                "couldn't find the OpenXR loader ({}); try enabling the \"static\" feature",
+                e
+            ))
+        })?
     };
 
     // OpenXR will fail to initialize if we ask for an extension that OpenXR can't provide! So we
@@ -266,81 +505,111 @@ pub fn init_openxr() -> (xr::Instance, xr::SystemId, EnvironmentBlendMode) {
     // extension is present, it's still possible you may not be able to use it. For example: the
     // hand tracking extension may be present, but the hand sensor might not be plugged in or turned
     // on. There are often additional checks that should be made before using certain features!
-    let available_extensions = entry.enumerate_extensions().unwrap();
+    let available_extensions = entry.enumerate_extensions()?;
 
     // If a required extension isn't present, you want to ditch out here! It's possible something
     // like your rendering API might not be provided by the active runtime. APIs like OpenGL don't
     // have universal support.
-    assert!(available_extensions.khr_vulkan_enable2);
+    if !available_extensions.khr_vulkan_enable2 {
+        return Err(KaleidoError::MissingExtension("XR_KHR_vulkan_enable2"));
+    }
 
-    // Initialize OpenXR with the extensions we've found!
-    let mut enabled_extensions = xr::ExtensionSet::default();
+    // Initialize OpenXR with the extensions we've found! Required extensions are passed through
+    // as-is, so a missing one surfaces as XR_ERROR_EXTENSION_NOT_PRESENT from create_instance.
+    let mut enabled_extensions = settings.required_extensions.clone();
     enabled_extensions.khr_vulkan_enable2 = true;
+    if settings.render_config.submit_depth.is_some() {
+        enabled_extensions.khr_composition_layer_depth =
+            available_extensions.khr_composition_layer_depth;
+    }
+    // Bindings are only suggested for controller profiles whose extension is enabled, so every
+    // one the runtime offers is switched on. Pickers can still switch them off again.
+    enabled_extensions.ext_hp_mixed_reality_controller =
+        available_extensions.ext_hp_mixed_reality_controller;
+    // The default `SpaceConfig` falls back to LOCAL_FLOOR, which an OpenXR 1.0 instance only
+    // offers through this extension.
+    enabled_extensions.ext_local_floor = available_extensions.ext_local_floor;
+    for pick in &settings.optional_extensions {
+        pick(&available_extensions, &mut enabled_extensions);
+    }
+
+    // Layers are checked up front for the same reason as extensions.
+    let available_layers = entry.enumerate_layers()?;
+    for layer in &settings.api_layers {
+        if !available_layers.iter().any(|l| &l.layer_name == layer) {
+            return Err(KaleidoError::MissingApiLayer(layer.clone()));
+        }
+    }
+    let api_layers = settings.api_layers.iter().map(String::as_str).collect::<Vec<_>>();
 
     let xr_instance = entry
         .create_instance(
             &xr::ApplicationInfo {
-                application_name: "openxrs example",
-                application_version: 0,
-                engine_name: "openxrs example",
-                engine_version: 0,
+                application_name: &settings.application_name,
+                application_version: settings.application_version,
+                engine_name: &settings.engine_name,
+                engine_version: settings.engine_version,
                 api_version: xr::Version::new(1, 0, 0),
             },
             &enabled_extensions,
-            &[],
-        )
-        .unwrap();
-    let instance_props = xr_instance.properties().unwrap();
+            &api_layers,
+        )?;
+    let instance_props = xr_instance.properties()?;
     println!(
         "loaded OpenXR runtime: {} {}",
         instance_props.runtime_name, instance_props.runtime_version
     );
 
     // Request a form factor from the device (HMD, Handheld, etc.)
-    let system = xr_instance
-        .system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
-        .unwrap();
+    let system = xr_instance.system(settings.form_factor)?;
 
-    // Check what blend mode is valid for this device (opaque vs transparent displays). We'll just
-    // take the first one available!
-    let environment_blend_mode = xr_instance
-        .enumerate_environment_blend_modes(system, VIEW_TYPE)
-        .unwrap()[0];
+    // Check what blend mode is valid for this device (opaque vs transparent displays). We take the
+    // first preferred mode the runtime supports, or else whatever it lists first.
+    let blend_modes = xr_instance.enumerate_environment_blend_modes(system, settings.render_config.view_type)?;
+    let environment_blend_mode = settings
+        .blend_modes
+        .iter()
+        .find(|mode| blend_modes.contains(mode))
+        .or_else(|| blend_modes.first())
+        .copied()
+        .ok_or(KaleidoError::NoEnvironmentBlendMode)?;
 
     // OpenXR wants to ensure apps are using the correct graphics card and Vulkan features and
     // extensions, so the instance and device MUST be set up before Instance::create_session.
 
-    let vk_target_version = vk::make_api_version(0, 1, 1, 0); // Vulkan 1.1 guarantees multiview support
-    let vk_target_version_xr = xr::Version::new(1, 1, 0);
+    let vk_target_version_xr = settings.vulkan_version;
 
     let reqs = xr_instance
-        .graphics_requirements::<xr::Vulkan>(system)
-        .unwrap();
+        .graphics_requirements::<xr::Vulkan>(system)?;
 
     if vk_target_version_xr < reqs.min_api_version_supported
         || vk_target_version_xr.major() > reqs.max_api_version_supported.major()
     {
-        panic!(
-            "OpenXR runtime requires Vulkan version > {}, < {}.0.0",
-            reqs.min_api_version_supported,
-            reqs.max_api_version_supported.major() + 1
-        );
+        return Err(KaleidoError::UnsupportedVulkanVersion {
+            requested: vk_target_version_xr,
+            min: reqs.min_api_version_supported,
+            max: reqs.max_api_version_supported,
+        });
     }
 
-    (xr_instance, system, environment_blend_mode)
+    Ok((xr_instance, system, environment_blend_mode))
 }
-pub fn setup_openxr(xr_instance: &xr::Instance, system: xr::SystemId, session: &Session<Vulkan>) -> (xr::Space, xr::ActionSet, xr::Action<xr::Posef>, xr::Action<xr::Posef>, xr::Space, xr::Space) {
+
+/// Pose actions for both grips bound on the simple controller only, and a STAGE space that fails
+/// on runtimes without one.
+#[deprecated(
+    note = "use `kspace::ReferenceSpace` with a `SpaceConfig` for the space and `kinput::ControllerInput` for the grips"
+)]
+#[allow(clippy::type_complexity)]
+pub fn setup_openxr(xr_instance: &xr::Instance, _system: xr::SystemId, session: &Session<Vulkan>) -> Result<(xr::Space, xr::ActionSet, xr::Action<xr::Posef>, xr::Action<xr::Posef>, xr::Space, xr::Space)> {
     // Create an action set to encapsulate our actions
     let action_set = xr_instance
-        .create_action_set("input", "input pose information", 0)
-        .unwrap();
+        .create_action_set("input", "input pose information", 0)?;
 
     let right_action = action_set
-        .create_action::<xr::Posef>("right_hand", "Right Hand Controller", &[])
-        .unwrap();
+        .create_action::<xr::Posef>("right_hand", "Right Hand Controller", &[])?;
     let left_action = action_set
-        .create_action::<xr::Posef>("left_hand", "Left Hand Controller", &[])
-        .unwrap();
+        .create_action::<xr::Posef>("left_hand", "Left Hand Controller", &[])?;
 
     // Bind our actions to input devices using the given profile
     // If you want to access inputs specific to a particular device you may specify a different
@@ -348,167 +617,583 @@ pub fn setup_openxr(xr_instance: &xr::Instance, system: xr::SystemId, session: &
     xr_instance
         .suggest_interaction_profile_bindings(
             xr_instance
-                .string_to_path("/interaction_profiles/khr/simple_controller")
-                .unwrap(),
+                .string_to_path("/interaction_profiles/khr/simple_controller")?,
             &[
                 xr::Binding::new(
                     &right_action,
                     xr_instance
-                        .string_to_path("/user/hand/right/input/grip/pose")
-                        .unwrap(),
+                        .string_to_path("/user/hand/right/input/grip/pose")?,
                 ),
                 xr::Binding::new(
                     &left_action,
                     xr_instance
-                        .string_to_path("/user/hand/left/input/grip/pose")
-                        .unwrap(),
+                        .string_to_path("/user/hand/left/input/grip/pose")?,
                 ),
             ],
-        )
-        .unwrap();
+        )?;
 
     // Attach the action set to the session
-    session.attach_action_sets(&[&action_set]).unwrap();
+    session.attach_action_sets(&[&action_set])?;
 
     // Create an action space for each device we want to locate
     let right_space = right_action
-        .create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)
-        .unwrap();
+        .create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)?;
     let left_space = left_action
-        .create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)
-        .unwrap();
+        .create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)?;
 
     // OpenXR uses a couple different types of reference frames for positioning content; we need
     // to choose one for displaying our content! STAGE would be relative to the center of your
     // guardian system's bounds, and LOCAL would be relative to your device's starting location.
     let stage = session
-        .create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)
-        .unwrap();
+        .create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)?;
 
-    (stage, action_set, left_action, right_action, left_space, right_space)
+    Ok((stage, action_set, left_action, right_action, left_space, right_space))
 }
 
-pub fn create_commands(vk_device: &ash::Device, queue_family_index:u32) -> (vk::CommandPool, Vec<vk::CommandBuffer>, Vec<vk::Fence>) {
+pub fn create_commands(device: &Arc<DeviceContext>, config: &RenderConfig) -> Result<CommandRing> {
+    let vk_device = &device.device;
     unsafe {
         let cmd_pool = vk_device
             .create_command_pool(
                 &vk::CommandPoolCreateInfo::default()
-                    .queue_family_index(queue_family_index)
+                    .queue_family_index(device.queue_family_index)
                     .flags(
                         vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER
                             | vk::CommandPoolCreateFlags::TRANSIENT,
                     ),
                 None,
-            )
-            .unwrap();
-        let cmds = vk_device
-            .allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::default()
-                    .command_pool(cmd_pool)
-                    .command_buffer_count(PIPELINE_DEPTH),
-            )
-            .unwrap();
-        let fences = (0..PIPELINE_DEPTH)
-            .map(|_| {
-                vk_device
-                    .create_fence(
-                        &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
-                        None,
-                    )
-                    .unwrap()
-            })
-            .collect::<Vec<_>>();
+            )?;
+        // The ring owns the pool from here on, so fences are pushed into it one at a time and
+        // anything created before a failure is released by its `Drop`.
+        let mut ring = CommandRing::new(device.clone(), cmd_pool, Vec::new(), Vec::new());
+        ring.cmds = vk_device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::default()
+                .command_pool(cmd_pool)
+                .command_buffer_count(config.pipeline_depth),
+        )?;
+        for _ in 0..config.pipeline_depth {
+            ring.fences.push(vk_device.create_fence(
+                &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
+                None,
+            )?);
+        }
+
+        // GPU frame timing is optional; queues that can't write timestamps just report none.
+        let timestamp_bits = device
+            .instance
+            .get_physical_device_queue_family_properties(device.physical_device)
+            [device.queue_family_index as usize]
+            .timestamp_valid_bits;
+        if timestamp_bits > 0 {
+            ring.queries = Some(vk_device.create_query_pool(
+                &vk::QueryPoolCreateInfo::default()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(config.pipeline_depth * 2),
+                None,
+            )?);
+            ring.timestamp_period = device
+                .instance
+                .get_physical_device_properties(device.physical_device)
+                .limits
+                .timestamp_period;
+            ring.timestamp_mask = u64::MAX >> (64 - timestamp_bits);
+        }
 
-        (cmd_pool, cmds, fences)
+        Ok(ring)
     }
 }
 
-pub fn create_swapchain<'a>(swapchain: &'a mut Option<Swapchain>, xr_instance: &xr::Instance, vk_device: &ash::Device, render_pass: RenderPass, system: xr::SystemId, session: &Session<Vulkan>) -> &'a mut Swapchain
+/// Picks the first format in `preferences` that the runtime can create swapchains with.
+pub fn negotiate_color_format(
+    session: &Session<Vulkan>,
+    preferences: &[vk::Format],
+) -> Result<vk::Format> {
+    // The runtime lists its formats in its own order of preference, but we let the application's
+    // order win since it knows what its shaders expect.
+    let supported = session.enumerate_swapchain_formats()?;
+    preferences
+        .iter()
+        .copied()
+        .find(|format| supported.contains(&(format.as_raw() as u32)))
+        .ok_or(KaleidoError::NoSupportedSwapchainFormat)
+}
+
+/// Picks the first format in `config.depth_formats` usable for the configured depth source:
+/// supported by the device as a depth attachment for owned images, or listed by the runtime for
+/// a depth swapchain.
+pub fn negotiate_depth_format(
+    device: &DeviceContext,
+    session: &Session<Vulkan>,
+    config: &RenderConfig,
+) -> Result<vk::Format> {
+    let supported = match config.depth {
+        Some(DepthSource::XrSwapchain) => session.enumerate_swapchain_formats()?,
+        _ => Vec::new(),
+    };
+    config
+        .depth_formats
+        .iter()
+        .copied()
+        .find(|&format| match config.depth {
+            Some(DepthSource::XrSwapchain) => supported.contains(&(format.as_raw() as u32)),
+            _ => unsafe {
+                device
+                    .instance
+                    .get_physical_device_format_properties(device.physical_device, format)
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            },
+        })
+        .ok_or(KaleidoError::NoSupportedDepthFormat)
+}
+
+/// Returns the swapchain in `swapchain`, creating it first if there is none yet or if it was
+/// created at a different `config.render_scale`. Changing the scale between frames is all it takes
+/// to resize; the old swapchain and its framebuffers are released once the GPU is done with them.
+pub fn create_swapchain<'a>(swapchain: &'a mut Option<Swapchain>, xr_instance: &xr::Instance, device: &Arc<DeviceContext>, render_pass: &RenderPass, config: &RenderConfig, system: xr::SystemId, session: &Session<Vulkan>) -> Result<&'a mut Swapchain>
 {
-    swapchain.get_or_insert_with(|| {
-        // Now we need to find all the viewpoints we need to take care of! This is a
-        // property of the view configuration type; in this example we use PRIMARY_STEREO,
-        // so we should have 2 viewpoints.
-        //
-        // Because we are using multiview in this example, we require that all view
-        // dimensions are identical.
-        let views = xr_instance
-            .enumerate_view_configuration_views(system, VIEW_TYPE)
-            .unwrap();
-        assert_eq!(views.len(), VIEW_COUNT as usize);
-        assert_eq!(views[0], views[1]);
-
-        // Create a swapchain for the viewpoints! A swapchain is a set of texture buffers
-        // used for displaying to screen, typically this is a backbuffer and a front buffer,
-        // one for rendering data to, and one for displaying on-screen.
-        let resolution = vk::Extent2D {
-            width: views[0].recommended_image_rect_width,
-            height: views[0].recommended_image_rect_height,
-        };
-        let handle = session
-            .create_swapchain(&xr::SwapchainCreateInfo {
-                create_flags: xr::SwapchainCreateFlags::EMPTY,
-                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
-                    | xr::SwapchainUsageFlags::SAMPLED,
-                format: COLOR_FORMAT.as_raw() as _,
-                // The Vulkan graphics pipeline we create is not set up for multisampling,
-                // so we hardcode this to 1. If we used a proper multisampling setup, we
-                // could set this to `views[0].recommended_swapchain_sample_count`.
-                sample_count: 1,
-                width: resolution.width,
-                height: resolution.height,
-                face_count: 1,
-                array_size: VIEW_COUNT,
-                mip_count: 1,
-            })
-            .unwrap();
+    if swapchain.as_ref().is_some_and(|s| s.scale == config.render_scale) {
+        return Ok(swapchain.as_mut().unwrap());
+    }
+    // Drop the stale swapchain before allocating its replacement so both never coexist.
+    *swapchain = None;
 
-        // We'll want to track our own information about the swapchain, so we can draw stuff
-        // onto it! We'll also create a buffer for each generated texture here as well.
-        let images = handle.enumerate_images().unwrap();
+    // Now we need to find all the viewpoints we need to take care of! This is a
+    // property of the view configuration type; by default we use PRIMARY_STEREO, so we
+    // should have 2 viewpoints.
+    let views = xr_instance.enumerate_view_configuration_views(system, config.view_type)?;
+    if views.len() != config.view_count as usize {
+        return Err(KaleidoError::ViewCountMismatch {
+            expected: config.view_count,
+            found: views.len() as u32,
+        });
+    }
 
-        let buffers:Vec<Framebuffer> = images
-            .into_iter()
-            .map(|color_image| {
-                let color_image = vk::Image::from_raw(color_image);
-                let color = unsafe {
-                    vk_device
-                        .create_image_view(
-                            &vk::ImageViewCreateInfo::default()
-                                .image(color_image)
-                                .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
-                                .format(COLOR_FORMAT)
-                                .subresource_range(vk::ImageSubresourceRange {
-                                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                                    base_mip_level: 0,
-                                    level_count: 1,
-                                    base_array_layer: 0,
-                                    layer_count: VIEW_COUNT,
-                                }),
-                            None,
-                        )
-                }.unwrap();
-                let framebuffer = unsafe {
-                    vk_device
-                        .create_framebuffer(
-                            &vk::FramebufferCreateInfo::default()
-                                .render_pass(render_pass)
-                                .width(resolution.width)
-                                .height(resolution.height)
-                                .attachments(&[color])
-                                .layers(1), // Multiview handles addressing multiple layers
-                            None,
-                        )
-                }.unwrap();
-                Framebuffer { framebuffer, color }
-            })
-            .collect();
+    // Views may recommend different sizes, e.g. on headsets with canted displays or asymmetric
+    // FOVs. The array swapchain is sized to fit the largest and every view renders into the
+    // top-left corner of its layer. Multiview shares one viewport between all views, so there
+    // every view uses the full size; rendering per view lets each keep its own.
+    let scaled = views
+        .iter()
+        .map(|view| scaled_extent(view, config.render_scale))
+        .collect::<Vec<_>>();
+    let resolution = vk::Extent2D {
+        width: scaled.iter().map(|e| e.width).max().unwrap_or(1),
+        height: scaled.iter().map(|e| e.height).max().unwrap_or(1),
+    };
+    let view_extents = match config.view_mode {
+        ViewMode::Multiview => vec![resolution; scaled.len()],
+        ViewMode::PerView => scaled,
+    };
 
-        Swapchain {
-            handle,
-            resolution,
-            buffers: buffers
+    // Create a swapchain for the viewpoints! A swapchain is a set of texture buffers
+    // used for displaying to screen, typically this is a backbuffer and a front buffer,
+    // one for rendering data to, and one for displaying on-screen.
+    let handle = session.create_swapchain(&xr::SwapchainCreateInfo {
+        create_flags: xr::SwapchainCreateFlags::EMPTY,
+        usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+            | xr::SwapchainUsageFlags::SAMPLED,
+        format: config.color_format.as_raw() as _,
+        // Multisampling happens in our own transient attachments and is resolved into the
+        // swapchain image, so the runtime only ever sees single-sampled images.
+        sample_count: 1,
+        width: resolution.width,
+        height: resolution.height,
+        face_count: 1,
+        array_size: config.view_count,
+        mip_count: 1,
+    })?;
+
+    // With depth coming from the runtime we need a matching depth swapchain, one layer per view
+    // just like the color one.
+    let depth_handle = match config.depth {
+        Some(DepthSource::XrSwapchain) => Some(session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            format: config.depth_format.as_raw() as _,
+            sample_count: 1,
+            width: resolution.width,
+            height: resolution.height,
+            face_count: 1,
+            array_size: config.view_count,
+            mip_count: 1,
+        })?),
+        _ => None,
+    };
+
+    // We'll want to track our own information about the swapchain, so we can draw stuff
+    // onto it! We'll also create a buffer for each generated texture here as well.
+    let images = handle.enumerate_images()?;
+    let depth_images = match &depth_handle {
+        Some(depth_handle) => depth_handle.enumerate_images()?.into_iter().map(Some).collect(),
+        None => vec![None; images.len()],
+    };
+
+    // Multiview draws every view through one framebuffer spanning all layers; otherwise each
+    // view gets a framebuffer of its own over a single layer.
+    let passes = config.passes();
+    let layers_per_pass = config.view_count / passes;
+    let mut buffers = Vec::with_capacity(images.len() * passes as usize);
+    for (color_image, depth_image) in images.into_iter().zip(depth_images) {
+        for pass in 0..passes {
+            buffers.push(create_framebuffer(
+                device,
+                render_pass,
+                config,
+                view_extents[(pass * layers_per_pass) as usize],
+                vk::Image::from_raw(color_image),
+                depth_image.map(vk::Image::from_raw),
+                pass * layers_per_pass,
+                layers_per_pass,
+            )?);
         }
-    })
+    }
+
+    Ok(swapchain.insert(Swapchain::new(
+        device.clone(),
+        handle,
+        depth_handle,
+        buffers,
+        passes as usize,
+        resolution,
+        view_extents,
+        config.render_scale,
+    )))
 }
 
+/// The view's recommended size multiplied by `scale`, kept between one pixel and the largest
+/// size the runtime allows.
+fn scaled_extent(view: &xr::ViewConfigurationView, scale: f32) -> vk::Extent2D {
+    let scale = |recommended: u32, max: u32| {
+        ((recommended as f32 * scale).round() as u32).clamp(1, max.max(1))
+    };
+    vk::Extent2D {
+        width: scale(view.recommended_image_rect_width, view.max_image_rect_width),
+        height: scale(view.recommended_image_rect_height, view.max_image_rect_height),
+    }
+}
+
+/// Creates the framebuffer rendering into the top-left `extent` of `layer_count` layers of a
+/// swapchain image starting at `base_layer`, along with any depth and MSAA attachments it needs.
+#[allow(clippy::too_many_arguments)]
+fn create_framebuffer(
+    device: &Arc<DeviceContext>,
+    render_pass: &RenderPass,
+    config: &RenderConfig,
+    extent: vk::Extent2D,
+    color_image: vk::Image,
+    depth_image: Option<vk::Image>,
+    base_layer: u32,
+    layer_count: u32,
+) -> Result<Framebuffer> {
+    let color = create_array_view(
+        device,
+        color_image,
+        config.color_format,
+        vk::ImageAspectFlags::COLOR,
+        base_layer,
+        layer_count,
+    )?;
+    // From here on the framebuffer owns whatever has been created, so an early return
+    // releases it.
+    let mut buffer = Framebuffer::new(device.clone(), vk::Framebuffer::null(), color, None, None);
+
+    // Images we allocate ourselves only need the layers this framebuffer renders.
+    let (depth_image, depth_base_layer) = match (config.depth, depth_image) {
+        (Some(DepthSource::XrSwapchain), Some(image)) => (Some(image), base_layer),
+        (Some(DepthSource::Owned), _) => {
+            let image = create_image(
+                device,
+                extent,
+                layer_count,
+                config.depth_format,
+                config.sample_count(),
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            )?;
+            let handle = image.handle;
+            buffer.depth_image = Some(image);
+            (Some(handle), 0)
+        }
+        _ => (None, 0),
+    };
+    if let Some(depth_image) = depth_image {
+        buffer.depth = Some(create_array_view(
+            device,
+            depth_image,
+            config.depth_format,
+            depth_aspect(config.depth_format),
+            depth_base_layer,
+            layer_count,
+        )?);
+    }
+
+    if config.multisampled() {
+        let image = create_image(
+            device,
+            extent,
+            layer_count,
+            config.color_format,
+            config.sample_count(),
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        )?;
+        let handle = image.handle;
+        buffer.msaa_image = Some(image);
+        buffer.msaa = Some(create_array_view(
+            device,
+            handle,
+            config.color_format,
+            vk::ImageAspectFlags::COLOR,
+            0,
+            layer_count,
+        )?);
+    }
+
+    // Attachment order matches `create_render_pass`: color, depth, then the resolve
+    // target. With MSAA the swapchain image is the resolve target.
+    let attachments = match buffer.msaa {
+        Some(msaa) => [Some(msaa), buffer.depth, Some(buffer.color)],
+        None => [Some(buffer.color), buffer.depth, None],
+    }
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    buffer.framebuffer = unsafe {
+        device.device.create_framebuffer(
+            &vk::FramebufferCreateInfo::default()
+                .render_pass(render_pass.handle)
+                .width(extent.width)
+                .height(extent.height)
+                .attachments(&attachments)
+                .layers(1), // Multiview handles addressing multiple layers
+            None,
+        )
+    }?;
+    Ok(buffer)
+}
+
+/// Depth info for each view of `swapchain`, to be chained onto the projection views by
+/// [`projection_views`]. Empty unless `config` submits depth and the swapchain has a depth
+/// swapchain to submit.
+pub fn depth_infos(
+    swapchain: &Swapchain,
+    config: &RenderConfig,
+) -> Vec<xr::sys::CompositionLayerDepthInfoKHR> {
+    let (Some(range), Some(depth_handle)) = (config.submit_depth, &swapchain.depth_handle) else {
+        return Vec::new();
+    };
+    (0..config.view_count)
+        .map(|i| xr::sys::CompositionLayerDepthInfoKHR {
+            ty: xr::sys::CompositionLayerDepthInfoKHR::TYPE,
+            next: std::ptr::null(),
+            sub_image: xr::sys::SwapchainSubImage {
+                swapchain: depth_handle.as_raw(),
+                image_rect: swapchain.image_rect(i as usize),
+                image_array_index: i,
+            },
+            min_depth: 0.0,
+            max_depth: 1.0,
+            near_z: range.near,
+            far_z: range.far,
+        })
+        .collect()
+}
+
+/// One projection view per entry in `views`, each pointing at the area of its layer of
+/// `swapchain` it was rendered to. Depth infos from [`depth_infos`] are chained on when there
+/// are any.
+pub fn projection_views<'a>(
+    swapchain: &'a Swapchain,
+    views: &[xr::View],
+    depth_infos: &'a [xr::sys::CompositionLayerDepthInfoKHR],
+) -> Vec<xr::CompositionLayerProjectionView<'a, Vulkan>> {
+    views
+        .iter()
+        .enumerate()
+        .map(|(i, view)| {
+            let projection_view = xr::CompositionLayerProjectionView::new()
+                .pose(view.pose)
+                .fov(view.fov)
+                .sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(&swapchain.handle)
+                        .image_array_index(i as u32)
+                        .image_rect(swapchain.image_rect(i)),
+                );
+            match depth_infos.get(i) {
+                Some(depth_info) => {
+                    let mut raw = projection_view.into_raw();
+                    raw.next = depth_info as *const _ as *const _;
+                    // The depth info is borrowed for 'a, as long as the projection view itself.
+                    unsafe { xr::CompositionLayerProjectionView::from_raw(raw) }
+                }
+                None => projection_view,
+            }
+        })
+        .collect()
+}
+
+/// Creates a view covering `layer_count` layers of `image` starting at `base_layer`.
+fn create_array_view(
+    device: &DeviceContext,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    base_layer: u32,
+    layer_count: u32,
+) -> Result<vk::ImageView> {
+    let view = unsafe {
+        device.device.create_image_view(
+            &vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+                .format(format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: base_layer,
+                    layer_count,
+                }),
+            None,
+        )
+    }?;
+    Ok(view)
+}
+
+/// Creates a device-local 2D array image with one layer per view.
+pub fn create_image(
+    device: &Arc<DeviceContext>,
+    extent: vk::Extent2D,
+    layers: u32,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    usage: vk::ImageUsageFlags,
+) -> Result<Image> {
+    unsafe {
+        let handle = device.device.create_image(
+            &vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(layers)
+                .samples(samples)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED),
+            None,
+        )?;
+        // Owned by the wrapper before anything else can fail; destroying a null memory handle
+        // is allowed.
+        let mut image = Image::new(device.clone(), handle, vk::DeviceMemory::null());
+
+        let requirements = device.device.get_image_memory_requirements(handle);
+        let memory_type_index = find_memory_type(
+            device,
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        image.memory = device.device.allocate_memory(
+            &vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index),
+            None,
+        )?;
+        device.device.bind_image_memory(handle, image.memory, 0)?;
+        Ok(image)
+    }
+}
+
+/// Creates a host-visible, coherent buffer of `size` bytes for data the CPU rewrites often.
+pub fn create_buffer(
+    device: &Arc<DeviceContext>,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> Result<Buffer> {
+    unsafe {
+        let handle = device.device.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            None,
+        )?;
+        let mut buffer = Buffer::new(device.clone(), handle, vk::DeviceMemory::null(), size);
+
+        let requirements = device.device.get_buffer_memory_requirements(handle);
+        let memory_type_index = find_memory_type(
+            device,
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        buffer.memory = device.device.allocate_memory(
+            &vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index),
+            None,
+        )?;
+        device.device.bind_buffer_memory(handle, buffer.memory, 0)?;
+        Ok(buffer)
+    }
+}
+
+fn find_memory_type(
+    device: &DeviceContext,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<u32> {
+    let memory_properties = unsafe {
+        device
+            .instance
+            .get_physical_device_memory_properties(device.physical_device)
+    };
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(i, memory_type)| {
+            type_bits & (1 << i) != 0 && memory_type.property_flags.contains(properties)
+        })
+        .map(|(i, _)| i as u32)
+        .ok_or(KaleidoError::NoSuitableMemoryType)
+}
+
+/// The highest sample count no greater than `config.samples` that the device supports for every
+/// attachment `config` renders with.
+pub fn clamp_sample_count(device: &DeviceContext, config: &RenderConfig) -> u32 {
+    let limits = unsafe {
+        device
+            .instance
+            .get_physical_device_properties(device.physical_device)
+            .limits
+    };
+    let mut supported = limits.framebuffer_color_sample_counts;
+    if config.depth.is_some() {
+        supported &= limits.framebuffer_depth_sample_counts;
+    }
+
+    let mut samples = config.samples.max(1).next_power_of_two();
+    if samples > config.samples.max(1) {
+        samples >>= 1;
+    }
+    while samples > 1 && !supported.contains(vk::SampleCountFlags::from_raw(samples)) {
+        samples >>= 1;
+    }
+    samples
+}
+
+fn depth_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
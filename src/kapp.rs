@@ -0,0 +1,470 @@
+use std::{sync::Arc, time::Duration};
+
+use ash::vk;
+use openxr as xr;
+
+use crate::kabstract::{
+    create_commands, create_render_pass, create_swapchain, depth_infos, projection_views,
+};
+use crate::kcontext::{KaleidoContext, KaleidoContextBuilder};
+use crate::kerror::{KaleidoError, Result};
+use crate::kevents::{log_unhandled, EventBus};
+use crate::klifecycle::SessionLifecycle;
+use crate::kresolution::ResolutionController;
+use crate::kspace::{PlayArea, ReferenceSpace, SpaceChange, SpaceConfig};
+use crate::kstructs::{CommandRing, DeviceContext, RenderConfig, RenderPass, Swapchain};
+
+/// An application driven by [`run`].
+///
+/// Only `record` is required; every other hook has a do-nothing default.
+pub trait App {
+    /// Adjusts the context before it's built, e.g. to set the application name or a render
+    /// config.
+    fn configure(&self, builder: KaleidoContextBuilder) -> KaleidoContextBuilder {
+        builder
+    }
+
+    /// Called whenever a session is set up, before its first frame. This is the place to create
+    /// pipelines against `render_pass` and to attach action sets to the session. After a loss
+    /// it's called again for the replacement session, so everything from before has to go.
+    fn init(&mut self, _ctx: &KaleidoContext, _render_pass: &RenderPass) -> Result<()> {
+        Ok(())
+    }
+
+    /// Registers callbacks for session state changes, e.g. to pause simulation while the
+    /// session isn't visible. Called for every session, right after `init`.
+    fn lifecycle(&mut self, _lifecycle: &mut SessionLifecycle) {}
+
+    /// Subscribes handlers to OpenXR events. Called for every session, right after `lifecycle`.
+    fn events(&mut self, _events: &mut EventBus) {}
+
+    /// Called once per frame the runtime hands out, whether or not it ends up being rendered.
+    fn update(&mut self, _frame: &FrameContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Records the draws for one render pass instance covering `view_count` views, starting at
+    /// `render.view`. The pass has already been begun, with the viewport and scissor set.
+    fn record(&mut self, render: &mut RenderContext, view_count: u32) -> Result<()>;
+
+    /// Called after a session's last frame, before anything is torn down. Drop whatever was
+    /// created from the context here.
+    fn shutdown(&mut self, _ctx: &KaleidoContext) {}
+
+    /// Checked at the start of every frame; returning true asks the runtime to end the session.
+    fn exit_requested(&self) -> bool {
+        false
+    }
+
+    /// The reference space to run in, picked anew for every session. Defaults to STAGE, falling
+    /// back to LOCAL_FLOOR and then LOCAL.
+    fn reference_space(&self) -> SpaceConfig {
+        SpaceConfig::default()
+    }
+
+    /// Checked once per frame; returning true recenters the reference space on the user's
+    /// current position and heading.
+    fn recenter_requested(&mut self) -> bool {
+        false
+    }
+
+    /// Called when the reference space's origin moved, after recentering or when the runtime
+    /// announced a change with `ReferenceSpaceChangePending`. Poses the app keeps in the space
+    /// can be carried over with `SpaceChange::apply`.
+    fn reference_space_changed(&mut self, _change: &SpaceChange) {}
+
+    /// Color the swapchain images are cleared to.
+    fn clear_color(&self) -> [f32; 4] {
+        [0.0, 0.0, 0.0, 1.0]
+    }
+
+    /// Returns a controller to scale the render resolution with GPU load, or `None` to always
+    /// render at `RenderConfig::render_scale`.
+    fn resolution_controller(&self) -> Option<ResolutionController> {
+        None
+    }
+
+    /// Checked once per frame; returning a scale replaces `RenderConfig::render_scale` from the
+    /// next frame on, rebuilding the swapchain at the new size. Ignored while a
+    /// [`ResolutionController`] is in charge of the scale.
+    fn render_scale_requested(&mut self) -> Option<f32> {
+        None
+    }
+}
+
+/// What an [`App`] gets to see of the current frame.
+pub struct FrameContext<'a> {
+    pub session: &'a xr::Session<xr::Vulkan>,
+    /// Where the session is in its lifecycle, e.g. whether it currently has input focus.
+    pub lifecycle: &'a SessionLifecycle,
+    /// The reference space the views are located in and layers are submitted in.
+    pub space: &'a xr::Space,
+    /// The type of `space`, which may be a fallback from the app's preferred one.
+    pub space_type: xr::ReferenceSpaceType,
+    /// The play area in `space`, if the runtime reports one. Refreshed whenever the runtime
+    /// announces a change to the space.
+    pub play_area: Option<PlayArea>,
+    pub predicted_display_time: xr::Time,
+    pub predicted_display_period: xr::Duration,
+    pub views: &'a [xr::View],
+    /// Whether the frame will be rendered and submitted.
+    pub should_render: bool,
+}
+
+/// Everything an [`App`] needs to record into the current render pass instance.
+pub struct RenderContext<'a> {
+    pub device: &'a Arc<DeviceContext>,
+    pub cmd: vk::CommandBuffer,
+    pub config: &'a RenderConfig,
+    /// Index of the first view this pass renders.
+    pub view: u32,
+    /// Every view of the frame, located in the reference space.
+    pub views: &'a [xr::View],
+    /// Which frame in flight is being recorded, below `RenderConfig::pipeline_depth`. Resources
+    /// the CPU rewrites every frame need one copy per slot.
+    pub frame: usize,
+    /// Size of the area being rendered to.
+    pub extent: vk::Extent2D,
+}
+
+/// How long to wait between attempts to bring the runtime back up after losing it.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a session stopped running.
+enum SessionEnd {
+    /// The session exited normally, so the app is done.
+    Exit,
+    /// The session was lost but the instance is fine.
+    SessionLost,
+    /// The instance or the device was lost; everything has to be rebuilt.
+    InstanceLost,
+}
+
+/// Brings up a [`KaleidoContext`] configured by `app` and runs the frame loop until the session
+/// exits: event polling, session begin and end, frame pacing, command recording and layer
+/// submission.
+///
+/// Losing the session, instance or Vulkan device doesn't end the loop. The app is shut down, the
+/// lost objects are torn down, and they're rebuilt as soon as the runtime is available again,
+/// after which the app is initialized anew. Only `App::exit_requested` stops the waiting.
+pub fn run(mut app: impl App) -> Result<()> {
+    let mut ctx = app.configure(KaleidoContext::builder()).build()?;
+    loop {
+        let end = match run_session(&mut app, &mut ctx) {
+            Ok(end) => end,
+            Err(KaleidoError::Xr(xr::sys::Result::ERROR_SESSION_LOST)) => SessionEnd::SessionLost,
+            Err(
+                KaleidoError::Xr(xr::sys::Result::ERROR_INSTANCE_LOST)
+                | KaleidoError::Vulkan(vk::Result::ERROR_DEVICE_LOST),
+            ) => SessionEnd::InstanceLost,
+            Err(e) => return Err(e),
+        };
+
+        ctx = match end {
+            SessionEnd::Exit => return Ok(()),
+            SessionEnd::SessionLost => {
+                log::warn!("session lost, creating a new one");
+                match ctx.recreate_session() {
+                    Ok(ctx) => ctx,
+                    Err(e) => {
+                        log::warn!("couldn't recreate the session: {}", e);
+                        match rebuild(&app) {
+                            Some(ctx) => ctx,
+                            None => return Ok(()),
+                        }
+                    }
+                }
+            }
+            SessionEnd::InstanceLost => {
+                log::warn!("instance lost, waiting for the runtime to come back");
+                drop(ctx);
+                match rebuild(&app) {
+                    Some(ctx) => ctx,
+                    None => return Ok(()),
+                }
+            }
+        };
+    }
+}
+
+/// Builds a fresh context, retrying until the runtime can provide one. Returns `None` if the app
+/// asks to exit in the meantime.
+fn rebuild(app: &impl App) -> Option<KaleidoContext> {
+    loop {
+        if app.exit_requested() {
+            return None;
+        }
+        match app.configure(KaleidoContext::builder()).build() {
+            Ok(ctx) => return Some(ctx),
+            Err(e) => {
+                log::info!("runtime unavailable: {}", e);
+                std::thread::sleep(RECOVERY_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Sets up everything one session needs, initializes the app and runs frames until the session
+/// ends. The app is shut down again whichever way the frame loop ends.
+fn run_session(app: &mut impl App, ctx: &mut KaleidoContext) -> Result<SessionEnd> {
+    let render_pass = create_render_pass(&ctx.device, &ctx.render_config)?;
+    let mut space = ReferenceSpace::new(&ctx.session, &app.reference_space())?;
+    let mut commands = create_commands(&ctx.device, &ctx.render_config)?;
+    app.init(ctx, &render_pass)?;
+
+    let end = frame_loop(app, ctx, &render_pass, &mut space, &mut commands);
+    app.shutdown(ctx);
+    end
+}
+
+fn frame_loop(
+    app: &mut impl App,
+    ctx: &mut KaleidoContext,
+    render_pass: &RenderPass,
+    space: &mut ReferenceSpace,
+    commands: &mut CommandRing,
+) -> Result<SessionEnd> {
+    let mut resolution = app.resolution_controller();
+    let mut lifecycle = SessionLifecycle::new();
+    app.lifecycle(&mut lifecycle);
+    let mut events = EventBus::new();
+    app.events(&mut events);
+
+    let mut swapchain = None;
+    let mut event_storage = xr::EventDataBuffer::new();
+    // Set once the runtime has been asked to end the session, which it then does through
+    // STOPPING and EXITING.
+    let mut exiting = false;
+    loop {
+        if !exiting && app.exit_requested() {
+            log::info!("requesting exit");
+            match ctx.session.request_exit() {
+                Ok(()) => exiting = true,
+                Err(xr::sys::Result::ERROR_SESSION_NOT_RUNNING) => return Ok(SessionEnd::Exit),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        while let Some(event) = ctx.instance.poll_event(&mut event_storage)? {
+            use xr::Event::*;
+            let handled = events.dispatch(event);
+            match event {
+                SessionStateChanged(e) => {
+                    log::info!("entered state {:?}", e.state());
+                    if let Err(e) = lifecycle.transition(e.state()) {
+                        log::warn!("{}", e);
+                    }
+                    match e.state() {
+                        xr::SessionState::READY => {
+                            ctx.session.begin(ctx.render_config.view_type)?;
+                        }
+                        xr::SessionState::STOPPING => {
+                            ctx.session.end()?;
+                        }
+                        xr::SessionState::EXITING => return Ok(SessionEnd::Exit),
+                        xr::SessionState::LOSS_PENDING => return Ok(SessionEnd::SessionLost),
+                        _ => {}
+                    }
+                }
+                InstanceLossPending(_) => {
+                    return Ok(SessionEnd::InstanceLost);
+                }
+                ReferenceSpaceChangePending(e) => match space.change(e) {
+                    Some(change) => {
+                        log::info!("{:?} reference space is changing", change.ty);
+                        space.refresh_bounds()?;
+                        app.reference_space_changed(&change);
+                    }
+                    None if !handled => log_unhandled(&event),
+                    None => {}
+                },
+                _ if !handled => log_unhandled(&event),
+                _ => {}
+            }
+        }
+
+        if !lifecycle.is_running() {
+            std::thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        let frame_state = ctx.frame_waiter.wait()?;
+        ctx.frame_stream.begin()?;
+
+        if app.recenter_requested() {
+            if let Some(change) = space.recenter(frame_state.predicted_display_time)? {
+                app.reference_space_changed(&change);
+            }
+        }
+        let (_, views) = ctx.session.locate_views(
+            ctx.render_config.view_type,
+            frame_state.predicted_display_time,
+            space.space(),
+        )?;
+        app.update(&FrameContext {
+            session: &ctx.session,
+            lifecycle: &lifecycle,
+            space: space.space(),
+            space_type: space.ty(),
+            play_area: space.play_area(),
+            predicted_display_time: frame_state.predicted_display_time,
+            predicted_display_period: frame_state.predicted_display_period,
+            views: &views,
+            should_render: frame_state.should_render,
+        })?;
+
+        if !frame_state.should_render {
+            ctx.frame_stream.end(
+                frame_state.predicted_display_time,
+                ctx.environment_blend_mode,
+                &[],
+            )?;
+            continue;
+        }
+
+        let cmd = commands.acquire()?;
+        // Takes effect when the swapchain is fetched below.
+        match &mut resolution {
+            Some(controller) => {
+                if let Some(gpu_time) = commands.gpu_time() {
+                    ctx.render_config.render_scale =
+                        controller.update(gpu_time, frame_state.predicted_display_period);
+                }
+            }
+            None => {
+                if let Some(scale) = app.render_scale_requested() {
+                    ctx.render_config.render_scale = scale;
+                }
+            }
+        }
+
+        let previous_scale = swapchain
+            .as_ref()
+            .map(|swapchain: &Swapchain| swapchain.scale);
+        let swapchain = create_swapchain(
+            &mut swapchain,
+            &ctx.instance,
+            &ctx.device,
+            render_pass,
+            &ctx.render_config,
+            ctx.system,
+            &ctx.session,
+        )?;
+        if previous_scale != Some(swapchain.scale) {
+            // The frames still in flight were rendered at the old size, so their timings would
+            // count toward the new scale.
+            commands.discard_timings();
+        }
+        let image_index = swapchain.acquire()?;
+
+        record_frame(
+            app,
+            ctx,
+            render_pass,
+            swapchain,
+            image_index,
+            &views,
+            commands,
+            cmd,
+        )?;
+
+        swapchain.wait()?;
+        commands.submit()?;
+        swapchain.release()?;
+
+        let depth_infos = depth_infos(swapchain, &ctx.render_config);
+        let projection_views = projection_views(swapchain, &views, &depth_infos);
+        ctx.frame_stream.end(
+            frame_state.predicted_display_time,
+            ctx.environment_blend_mode,
+            &[&xr::CompositionLayerProjection::new()
+                .space(space.space())
+                .views(&projection_views)],
+        )?;
+        commands.advance();
+    }
+}
+
+/// Records one frame into `cmd`: one render pass instance per framebuffer of swapchain image
+/// `image_index`, each handed to the app to fill in.
+#[allow(clippy::too_many_arguments)]
+fn record_frame(
+    app: &mut impl App,
+    ctx: &KaleidoContext,
+    render_pass: &RenderPass,
+    swapchain: &Swapchain,
+    image_index: usize,
+    views: &[xr::View],
+    commands: &mut CommandRing,
+    cmd: vk::CommandBuffer,
+) -> Result<()> {
+    let vk_device = &ctx.device.device;
+    let config = &ctx.render_config;
+    let views_per_pass = config.view_count / config.passes();
+    let clear_values = config.clear_values(app.clear_color());
+    unsafe {
+        vk_device.begin_command_buffer(
+            cmd,
+            &vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+        )?;
+    }
+    commands.begin_timing(cmd);
+
+    for (pass, framebuffer) in swapchain.framebuffers(image_index).iter().enumerate() {
+        let view = pass as u32 * views_per_pass;
+        // Views may differ in size; each renders to the top-left corner of its layer.
+        let extent = swapchain.view_extents[view as usize];
+        let area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+        unsafe {
+            vk_device.cmd_begin_render_pass(
+                cmd,
+                &vk::RenderPassBeginInfo::default()
+                    .render_pass(render_pass.handle)
+                    .framebuffer(framebuffer.framebuffer)
+                    .render_area(area)
+                    .clear_values(&clear_values),
+                vk::SubpassContents::INLINE,
+            );
+            vk_device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            vk_device.cmd_set_scissor(cmd, 0, &[area]);
+        }
+
+        let recorded = app.record(
+            &mut RenderContext {
+                device: &ctx.device,
+                cmd,
+                config,
+                view,
+                views,
+                frame: commands.frame(),
+                extent,
+            },
+            views_per_pass,
+        );
+        unsafe {
+            vk_device.cmd_end_render_pass(cmd);
+        }
+        recorded?;
+    }
+
+    commands.end_timing(cmd);
+    unsafe {
+        vk_device.end_command_buffer(cmd)?;
+    }
+    Ok(())
+}
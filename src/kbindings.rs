@@ -0,0 +1,392 @@
+use openxr as xr;
+use serde::Deserialize;
+
+use crate::kerror::Result;
+use crate::kinput::Hand;
+
+/// A logical controller input. Apps map their actions to these once and [`Bindings`] turns that
+/// into suggestions for every profile in [`PROFILES`]. Action manifests name them in snake case,
+/// e.g. `"thumbstick_click"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    /// The main "use" input, e.g. the trigger.
+    Select,
+    Menu,
+    /// Analog trigger pull, 0 to 1.
+    Trigger,
+    /// Analog grip squeeze, 0 to 1.
+    Squeeze,
+    /// The 2D directional input: thumbstick, or trackpad on controllers without one.
+    Thumbstick,
+    ThumbstickClick,
+    /// A/X on controllers that have face buttons.
+    PrimaryButton,
+    /// B/Y on controllers that have face buttons.
+    SecondaryButton,
+    GripPose,
+    AimPose,
+    Haptic,
+}
+
+/// Component paths of one interaction profile, relative to the hand's user path.
+pub struct InteractionProfile {
+    /// Human-readable name of the controller.
+    pub name: &'static str,
+    pub path: &'static str,
+    /// Whether the instance has what the profile needs, i.e. the extension that introduces it.
+    pub available: fn(&xr::InstanceExtensions) -> bool,
+    /// Every component path the profile defines, as the OpenXR spec lists them; `None` for the
+    /// hand applies to both. The runtime rejects suggestions naming anything else.
+    pub paths: &'static [(Option<Hand>, &'static str)],
+    /// Bindings per control; `None` for the hand applies to both.
+    pub components: &'static [(Control, Option<Hand>, &'static str)],
+}
+
+impl InteractionProfile {
+    /// The component `control` maps to on `hand`, if the controller has one.
+    pub fn component(&self, control: Control, hand: Hand) -> Option<&'static str> {
+        self.components
+            .iter()
+            .find(|(c, h, _)| *c == control && h.is_none_or(|h| h == hand))
+            .map(|(_, _, component)| *component)
+    }
+
+    /// Whether the controller in `hand` has `component`, e.g. `input/trigger/value`.
+    pub fn has_path(&self, hand: Hand, component: &str) -> bool {
+        self.paths
+            .iter()
+            .any(|(h, path)| *path == component && h.is_none_or(|h| h == hand))
+    }
+
+    /// Looks up a profile by its path, e.g. one returned by the runtime.
+    pub fn find(path: &str) -> Option<&'static InteractionProfile> {
+        PROFILES.iter().find(|profile| profile.path == path)
+    }
+}
+
+use Control::*;
+use Hand::{Left, Right};
+
+const COMMON_POSES: [(Control, Option<Hand>, &str); 3] = [
+    (GripPose, None, "input/grip/pose"),
+    (AimPose, None, "input/aim/pose"),
+    (Haptic, None, "output/haptic"),
+];
+
+/// Every interaction profile kaleido knows bindings for.
+pub static PROFILES: [InteractionProfile; 6] = [
+    InteractionProfile {
+        name: "Khronos Simple Controller",
+        path: "/interaction_profiles/khr/simple_controller",
+        available: |_| true,
+        paths: &[
+            (None, "input/select/click"),
+            (None, "input/menu/click"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/select/click"),
+            (Menu, None, "input/menu/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+    InteractionProfile {
+        name: "Oculus Touch",
+        path: "/interaction_profiles/oculus/touch_controller",
+        available: |_| true,
+        paths: &[
+            (Some(Left), "input/x/click"),
+            (Some(Left), "input/x/touch"),
+            (Some(Left), "input/y/click"),
+            (Some(Left), "input/y/touch"),
+            (Some(Left), "input/menu/click"),
+            (Some(Right), "input/a/click"),
+            (Some(Right), "input/a/touch"),
+            (Some(Right), "input/b/click"),
+            (Some(Right), "input/b/touch"),
+            (Some(Right), "input/system/click"),
+            (None, "input/squeeze/value"),
+            (None, "input/trigger/value"),
+            (None, "input/trigger/touch"),
+            (None, "input/thumbstick"),
+            (None, "input/thumbstick/x"),
+            (None, "input/thumbstick/y"),
+            (None, "input/thumbstick/click"),
+            (None, "input/thumbstick/touch"),
+            (None, "input/thumbrest/touch"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/trigger/value"),
+            (Menu, Some(Left), "input/menu/click"),
+            (Trigger, None, "input/trigger/value"),
+            (Squeeze, None, "input/squeeze/value"),
+            (Thumbstick, None, "input/thumbstick"),
+            (ThumbstickClick, None, "input/thumbstick/click"),
+            (PrimaryButton, Some(Left), "input/x/click"),
+            (PrimaryButton, Some(Right), "input/a/click"),
+            (SecondaryButton, Some(Left), "input/y/click"),
+            (SecondaryButton, Some(Right), "input/b/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+    InteractionProfile {
+        name: "Valve Index",
+        path: "/interaction_profiles/valve/index_controller",
+        available: |_| true,
+        paths: &[
+            (None, "input/system/click"),
+            (None, "input/system/touch"),
+            (None, "input/a/click"),
+            (None, "input/a/touch"),
+            (None, "input/b/click"),
+            (None, "input/b/touch"),
+            (None, "input/squeeze/value"),
+            (None, "input/squeeze/force"),
+            (None, "input/trigger/click"),
+            (None, "input/trigger/value"),
+            (None, "input/trigger/touch"),
+            (None, "input/thumbstick"),
+            (None, "input/thumbstick/x"),
+            (None, "input/thumbstick/y"),
+            (None, "input/thumbstick/click"),
+            (None, "input/thumbstick/touch"),
+            (None, "input/trackpad"),
+            (None, "input/trackpad/x"),
+            (None, "input/trackpad/y"),
+            (None, "input/trackpad/force"),
+            (None, "input/trackpad/touch"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/trigger/click"),
+            (Menu, None, "input/b/click"),
+            (Trigger, None, "input/trigger/value"),
+            (Squeeze, None, "input/squeeze/value"),
+            (Thumbstick, None, "input/thumbstick"),
+            (ThumbstickClick, None, "input/thumbstick/click"),
+            (PrimaryButton, None, "input/a/click"),
+            (SecondaryButton, None, "input/b/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+    InteractionProfile {
+        name: "HTC Vive",
+        path: "/interaction_profiles/htc/vive_controller",
+        available: |_| true,
+        paths: &[
+            (None, "input/system/click"),
+            (None, "input/squeeze/click"),
+            (None, "input/menu/click"),
+            (None, "input/trigger/click"),
+            (None, "input/trigger/value"),
+            (None, "input/trackpad"),
+            (None, "input/trackpad/x"),
+            (None, "input/trackpad/y"),
+            (None, "input/trackpad/click"),
+            (None, "input/trackpad/touch"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/trigger/click"),
+            (Menu, None, "input/menu/click"),
+            (Trigger, None, "input/trigger/value"),
+            (Squeeze, None, "input/squeeze/click"),
+            (Thumbstick, None, "input/trackpad"),
+            (ThumbstickClick, None, "input/trackpad/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+    InteractionProfile {
+        name: "Windows Mixed Reality",
+        path: "/interaction_profiles/microsoft/motion_controller",
+        available: |_| true,
+        paths: &[
+            (None, "input/menu/click"),
+            (None, "input/squeeze/click"),
+            (None, "input/trigger/value"),
+            (None, "input/thumbstick"),
+            (None, "input/thumbstick/x"),
+            (None, "input/thumbstick/y"),
+            (None, "input/thumbstick/click"),
+            (None, "input/trackpad"),
+            (None, "input/trackpad/x"),
+            (None, "input/trackpad/y"),
+            (None, "input/trackpad/click"),
+            (None, "input/trackpad/touch"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/trigger/value"),
+            (Menu, None, "input/menu/click"),
+            (Trigger, None, "input/trigger/value"),
+            (Squeeze, None, "input/squeeze/click"),
+            (Thumbstick, None, "input/thumbstick"),
+            (ThumbstickClick, None, "input/thumbstick/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+    InteractionProfile {
+        name: "HP Reverb G2",
+        path: "/interaction_profiles/hp/mixed_reality_controller",
+        available: |exts| exts.ext_hp_mixed_reality_controller.is_some(),
+        paths: &[
+            (Some(Left), "input/x/click"),
+            (Some(Left), "input/y/click"),
+            (Some(Right), "input/a/click"),
+            (Some(Right), "input/b/click"),
+            (None, "input/menu/click"),
+            (None, "input/squeeze/value"),
+            (None, "input/trigger/value"),
+            (None, "input/thumbstick"),
+            (None, "input/thumbstick/x"),
+            (None, "input/thumbstick/y"),
+            (None, "input/thumbstick/click"),
+            (None, "input/grip/pose"),
+            (None, "input/aim/pose"),
+            (None, "output/haptic"),
+        ],
+        components: &[
+            (Select, None, "input/trigger/value"),
+            (Menu, None, "input/menu/click"),
+            (Trigger, None, "input/trigger/value"),
+            (Squeeze, None, "input/squeeze/value"),
+            (Thumbstick, None, "input/thumbstick"),
+            (ThumbstickClick, None, "input/thumbstick/click"),
+            (PrimaryButton, Some(Left), "input/x/click"),
+            (PrimaryButton, Some(Right), "input/a/click"),
+            (SecondaryButton, Some(Left), "input/y/click"),
+            (SecondaryButton, Some(Right), "input/b/click"),
+            COMMON_POSES[0],
+            COMMON_POSES[1],
+            COMMON_POSES[2],
+        ],
+    },
+];
+
+type MakeBinding<'a> = Box<dyn Fn(xr::Path) -> xr::Binding<'a> + 'a>;
+
+/// An app's mapping from its actions to logical controls, suggested to the runtime for every
+/// known profile at once.
+///
+/// Actions should be created with both hands as sub-action paths, as `ActionSet::action` does.
+#[derive(Default)]
+pub struct Bindings<'a> {
+    entries: Vec<(MakeBinding<'a>, Control)>,
+}
+
+impl<'a> Bindings<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind<T: xr::ActionTy>(&mut self, action: &'a xr::Action<T>, control: Control) -> &mut Self {
+        self.entries
+            .push((Box::new(move |path| xr::Binding::new(action, path)), control));
+        self
+    }
+
+    /// Suggests the bindings for every profile in [`PROFILES`] the instance supports. Controls a
+    /// controller doesn't have are skipped for that profile.
+    pub fn suggest(&self, instance: &xr::Instance) -> Result<()> {
+        for profile in &PROFILES {
+            if !(profile.available)(instance.exts()) {
+                log::info!(
+                    "not suggesting bindings for {}, the instance doesn't support it",
+                    profile.name
+                );
+                continue;
+            }
+            let mut bindings = Vec::new();
+            for (make_binding, control) in &self.entries {
+                for hand in Hand::BOTH {
+                    if let Some(component) = profile.component(*control, hand) {
+                        let path = instance.string_to_path(&format!("{}/{}", hand.path(), component))?;
+                        bindings.push(make_binding(path));
+                    }
+                }
+            }
+            if !bindings.is_empty() {
+                instance.suggest_interaction_profile_bindings(
+                    instance.string_to_path(profile.path)?,
+                    &bindings,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The profile the runtime currently uses for `hand`, or `None` if nothing is bound or the
+/// profile isn't one kaleido knows. Worth re-querying on `InteractionProfileChanged`.
+pub fn active_profile(
+    instance: &xr::Instance,
+    session: &xr::Session<xr::Vulkan>,
+    hand: Hand,
+) -> Result<Option<&'static InteractionProfile>> {
+    let profile = session.current_interaction_profile(instance.string_to_path(hand.path())?)?;
+    if profile == xr::Path::NULL {
+        return Ok(None);
+    }
+    Ok(InteractionProfile::find(&instance.path_to_string(profile)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_tables_only_use_paths_the_profiles_have() {
+        for profile in &PROFILES {
+            for (control, hand, component) in profile.components {
+                let hands = match hand {
+                    Some(hand) => vec![*hand],
+                    None => Hand::BOTH.to_vec(),
+                };
+                for hand in hands {
+                    assert!(
+                        profile.has_path(hand, component),
+                        "{:?} on {} maps to {}/{}, which isn't in its paths",
+                        control,
+                        profile.name,
+                        hand.path(),
+                        component
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paths_are_per_hand() {
+        let touch = InteractionProfile::find("/interaction_profiles/oculus/touch_controller")
+            .unwrap();
+        assert!(touch.has_path(Left, "input/x/click"));
+        assert!(!touch.has_path(Right, "input/x/click"));
+        assert!(touch.has_path(Right, "input/trigger/value"));
+
+        let vive = InteractionProfile::find("/interaction_profiles/htc/vive_controller").unwrap();
+        assert!(!vive.has_path(Right, "input/a/click"));
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use ash::vk;
+use openxr as xr;
+
+use crate::kabstract::{create_boundary_pipeline, create_buffer};
+use crate::kapp::RenderContext;
+use crate::kerror::{KaleidoError, Result};
+use crate::kmath::{
+    add, inverse, length, mul_mat4, pose_matrix, projection, rotate, scale, sub, vec3,
+};
+use crate::kspace::PlayArea;
+use crate::kstructs::{Buffer, DepthRange, DeviceContext, Pipeline, RenderConfig, RenderPass};
+
+/// Bytes per vertex: a vec3 position followed by a vec4 color.
+pub(crate) const VERTEX_SIZE: u32 = 7 * 4;
+/// Views the multiview shader has a matrix for; the per-view one only ever reads the first.
+const MAX_VIEWS: u32 = 2;
+/// The shaders' `mat4 view_proj[2]`, one matrix per view of a stereo pair.
+pub(crate) const PUSH_CONSTANTS_SIZE: u32 = MAX_VIEWS * 64;
+
+/// Near and far planes used when the render config doesn't say which ones the app renders with.
+const DEFAULT_DEPTH_RANGE: DepthRange = DepthRange {
+    near: 0.05,
+    far: 100.0,
+};
+
+/// Draws the edges of the play area as walls of grid lines that fade in as the user gets close.
+///
+/// Create it in `App::init` against the render pass, feed it the play area and the tracked
+/// positions to keep inside, typically the head and the controllers, in `App::update`, and call
+/// [`BoundaryGrid::record`] from `App::record`. Nothing is drawn while the runtime reports no play
+/// area or everything is far enough from its edges.
+pub struct BoundaryGrid {
+    pub color: [f32; 3],
+    /// Height of the walls above the floor, in meters.
+    pub height: f32,
+    /// Distance between neighboring grid lines, in meters.
+    pub spacing: f32,
+    /// Distance from the edge at which the grid starts fading in.
+    pub fade_start: f32,
+    /// Distance from the edge at which it's fully opaque.
+    pub fade_end: f32,
+    /// The projection's near and far planes, which have to match the app's for the depth test
+    /// against its scene to work. Taken from `RenderConfig::submit_depth` when set.
+    pub depth_range: DepthRange,
+    pipeline: Pipeline,
+    /// One vertex buffer per frame in flight, grown as needed.
+    buffers: Vec<Option<Buffer>>,
+    vertices: Vec<f32>,
+    opacity: f32,
+}
+
+impl BoundaryGrid {
+    /// Fails with [`KaleidoError::TooManyViews`] when `config` draws more than two views in one
+    /// multiview pass, which the grid's shader has no matrices for.
+    pub fn new(
+        device: &Arc<DeviceContext>,
+        render_pass: &RenderPass,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let views_per_pass = config.view_count / config.passes();
+        if views_per_pass > MAX_VIEWS {
+            return Err(KaleidoError::TooManyViews {
+                max: MAX_VIEWS,
+                found: views_per_pass,
+            });
+        }
+        Ok(Self {
+            color: [0.0, 0.8, 1.0],
+            height: 2.5,
+            spacing: 0.25,
+            fade_start: 0.6,
+            fade_end: 0.15,
+            depth_range: config.submit_depth.unwrap_or(DEFAULT_DEPTH_RANGE),
+            pipeline: create_boundary_pipeline(device, render_pass, config)?,
+            buffers: (0..config.pipeline_depth).map(|_| None).collect(),
+            vertices: Vec::new(),
+            opacity: 0.0,
+        })
+    }
+
+    /// How visible the grid is as of the last update, from 0 (hidden) to 1.
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Rebuilds the grid for `play_area` and fades it by how close the nearest of `points` is to
+    /// an edge. Points outside the area count as on the edge. Both are in the reference space.
+    pub fn update(&mut self, play_area: Option<&PlayArea>, points: &[xr::Vector3f]) {
+        self.vertices.clear();
+        let Some(area) = play_area else {
+            self.opacity = 0.0;
+            return;
+        };
+        let nearest = points
+            .iter()
+            .map(|point| area.distance_to_edge(*point))
+            .fold(f32::INFINITY, f32::min);
+        let span = (self.fade_start - self.fade_end).max(f32::EPSILON);
+        self.opacity = ((self.fade_start - nearest) / span).clamp(0.0, 1.0);
+        if self.opacity == 0.0 {
+            return;
+        }
+
+        let color = [self.color[0], self.color[1], self.color[2], self.opacity];
+        let up = rotate(area.pose.orientation, vec3(0.0, 1.0, 0.0));
+        let rows = (self.height / self.spacing).round().max(1.0) as usize;
+        let corners = area.corners();
+        for (i, &start) in corners.iter().enumerate() {
+            let end = corners[(i + 1) % corners.len()];
+            let wall = sub(end, start);
+            let columns = (length(wall) / self.spacing).round().max(1.0) as usize;
+            // The last column is the next wall's first, so each corner gets one line.
+            for column in 0..columns {
+                let bottom = add(start, scale(wall, column as f32 / columns as f32));
+                self.line(bottom, add(bottom, scale(up, self.height)), color);
+            }
+            for row in 0..=rows {
+                let lift = scale(up, self.height * row as f32 / rows as f32);
+                self.line(add(start, lift), add(end, lift), color);
+            }
+        }
+    }
+
+    /// Draws the grid into the current render pass. Call it with the arguments `App::record`
+    /// got; the vertices are uploaded on the frame's first pass.
+    pub fn record(&mut self, render: &RenderContext, view_count: u32) -> Result<()> {
+        let first = render.view as usize;
+        let views = match render.views.get(first..first + view_count as usize) {
+            Some(views) if !self.vertices.is_empty() => views,
+            _ => return Ok(()),
+        };
+
+        if render.view == 0 {
+            let data: Vec<u8> = self.vertices.iter().flat_map(|f| f.to_ne_bytes()).collect();
+            let buffer = &mut self.buffers[render.frame];
+            // The ring waited for this slot's last frame, so its buffer is free to replace.
+            if buffer
+                .as_ref()
+                .is_none_or(|buffer| buffer.size < data.len() as u64)
+            {
+                *buffer = Some(create_buffer(
+                    render.device,
+                    (data.len() as u64).next_power_of_two(),
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                )?);
+            }
+            if let Some(buffer) = buffer {
+                buffer.write(&data)?;
+            }
+        }
+        let Some(buffer) = &self.buffers[render.frame] else {
+            return Ok(());
+        };
+
+        let DepthRange { near, far } = self.depth_range;
+        let view_proj: Vec<u8> = views
+            .iter()
+            .flat_map(|view| {
+                mul_mat4(
+                    &projection(view.fov, near, far),
+                    &pose_matrix(inverse(view.pose)),
+                )
+            })
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+
+        let vk_device = &render.device.device;
+        unsafe {
+            vk_device.cmd_bind_pipeline(
+                render.cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.handle,
+            );
+            vk_device.cmd_bind_vertex_buffers(render.cmd, 0, &[buffer.handle], &[0]);
+            vk_device.cmd_push_constants(
+                render.cmd,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                &view_proj,
+            );
+            vk_device.cmd_draw(render.cmd, self.vertex_count(), 1, 0, 0);
+        }
+        Ok(())
+    }
+
+    fn vertex_count(&self) -> u32 {
+        (self.vertices.len() * 4 / VERTEX_SIZE as usize) as u32
+    }
+
+    fn line(&mut self, a: xr::Vector3f, b: xr::Vector3f, color: [f32; 4]) {
+        for point in [a, b] {
+            self.vertices.extend_from_slice(&[point.x, point.y, point.z]);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+}
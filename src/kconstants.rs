@@ -1,21 +1,23 @@
-use std::{
-    io::Cursor,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
-};
-
-use ash::{
-    util::read_spv,
-    vk::{self, Handle},
-};
+use ash::vk;
 use openxr as xr;
 
+// Defaults for `RenderConfig`; pass a different config to the context builder to override them.
 
 pub const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+/// Swapchain formats to try, best first: 8-bit sRGB, BGRA sRGB, 10-bit, FP16 HDR
+pub const COLOR_FORMAT_PREFERENCES: [vk::Format; 4] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
 pub const VIEW_COUNT: u32 = 2;
 pub const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+/// Depth formats to try, best first
+pub const DEPTH_FORMAT_PREFERENCES: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
 /// Maximum number of frames in flight
 pub const PIPELINE_DEPTH: u32 = 2;
\ No newline at end of file
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use ash::vk::{self, Handle};
+use openxr as xr;
+
+use crate::kabstract::{
+    clamp_sample_count, init_openxr, init_vulkan, negotiate_color_format, negotiate_depth_format,
+};
+use crate::kerror::Result;
+use crate::kstructs::{DepthSource, DeviceContext, RenderConfig};
+
+type ExtensionPicker = Box<dyn Fn(&xr::ExtensionSet, &mut xr::ExtensionSet)>;
+
+/// Everything needed to start rendering: the OpenXR instance and system, the Vulkan device the
+/// runtime asked for and a session tying the two together.
+///
+/// Fields are declared in the order they have to be torn down, so dropping the context releases
+/// the session before the device and the device before the instance.
+pub struct KaleidoContext {
+    pub frame_stream: xr::FrameStream<xr::Vulkan>,
+    pub frame_waiter: xr::FrameWaiter,
+    pub session: xr::Session<xr::Vulkan>,
+    pub device: Arc<DeviceContext>,
+    pub system: xr::SystemId,
+    pub environment_blend_mode: xr::EnvironmentBlendMode,
+    pub render_config: RenderConfig,
+    pub instance: xr::Instance,
+}
+
+impl KaleidoContext {
+    pub fn builder() -> KaleidoContextBuilder {
+        KaleidoContextBuilder::default()
+    }
+
+    /// Replaces a lost session with a fresh one on the same instance and device. Anything
+    /// created from the old session has to be dropped beforehand.
+    ///
+    /// The context is consumed because the old session must be gone before the runtime hands
+    /// out a new one; on failure everything is torn down and the caller has to start over from
+    /// the builder.
+    pub fn recreate_session(self) -> Result<KaleidoContext> {
+        let KaleidoContext {
+            frame_stream,
+            frame_waiter,
+            session,
+            device,
+            system,
+            environment_blend_mode,
+            render_config,
+            instance,
+        } = self;
+        drop((frame_stream, frame_waiter, session));
+
+        let (session, frame_waiter, frame_stream) = create_session(&instance, system, &device)?;
+        Ok(KaleidoContext {
+            frame_stream,
+            frame_waiter,
+            session,
+            device,
+            system,
+            environment_blend_mode,
+            render_config,
+            instance,
+        })
+    }
+}
+
+fn create_session(
+    instance: &xr::Instance,
+    system: xr::SystemId,
+    device: &DeviceContext,
+) -> Result<(
+    xr::Session<xr::Vulkan>,
+    xr::FrameWaiter,
+    xr::FrameStream<xr::Vulkan>,
+)> {
+    let session = unsafe {
+        instance.create_session::<xr::Vulkan>(
+            system,
+            &xr::vulkan::SessionCreateInfo {
+                instance: device.instance.handle().as_raw() as _,
+                physical_device: device.physical_device.as_raw() as _,
+                device: device.device.handle().as_raw() as _,
+                queue_family_index: device.queue_family_index,
+                queue_index: 0,
+            },
+        )
+    }?;
+    Ok(session)
+}
+
+/// Collects the application's requirements before any OpenXR or Vulkan object is created.
+pub struct KaleidoContextBuilder {
+    pub(crate) application_name: String,
+    pub(crate) application_version: u32,
+    pub(crate) engine_name: String,
+    pub(crate) engine_version: u32,
+    pub(crate) required_extensions: xr::ExtensionSet,
+    pub(crate) optional_extensions: Vec<ExtensionPicker>,
+    pub(crate) api_layers: Vec<String>,
+    pub(crate) form_factor: xr::FormFactor,
+    pub(crate) blend_modes: Vec<xr::EnvironmentBlendMode>,
+    pub(crate) vulkan_version: xr::Version,
+    pub(crate) render_config: RenderConfig,
+}
+
+impl Default for KaleidoContextBuilder {
+    fn default() -> Self {
+        Self {
+            application_name: "kaleido".into(),
+            application_version: 0,
+            engine_name: "kaleido".into(),
+            engine_version: 0,
+            required_extensions: xr::ExtensionSet::default(),
+            optional_extensions: Vec::new(),
+            api_layers: Vec::new(),
+            form_factor: xr::FormFactor::HEAD_MOUNTED_DISPLAY,
+            blend_modes: Vec::new(),
+            // Vulkan 1.1 guarantees multiview support
+            vulkan_version: xr::Version::new(1, 1, 0),
+            render_config: RenderConfig::default(),
+        }
+    }
+}
+
+impl KaleidoContextBuilder {
+    pub fn application(mut self, name: &str, version: u32) -> Self {
+        self.application_name = name.into();
+        self.application_version = version;
+        self
+    }
+
+    pub fn engine(mut self, name: &str, version: u32) -> Self {
+        self.engine_name = name.into();
+        self.engine_version = version;
+        self
+    }
+
+    /// Extensions the application can't run without. `khr_vulkan_enable2` is always added.
+    pub fn required_extensions(mut self, extensions: xr::ExtensionSet) -> Self {
+        self.required_extensions = extensions;
+        self
+    }
+
+    /// Registers a callback that gets the runtime's available extensions and switches on
+    /// whichever optional ones it wants, e.g.
+    /// `|available, enabled| enabled.ext_hand_tracking = available.ext_hand_tracking`.
+    ///
+    /// Extensions that add controller profiles from `kbindings::PROFILES`, and XR_EXT_local_floor
+    /// for the LOCAL_FLOOR reference space, are already on whenever the runtime has them.
+    pub fn optional_extensions(
+        mut self,
+        pick: impl Fn(&xr::ExtensionSet, &mut xr::ExtensionSet) + 'static,
+    ) -> Self {
+        self.optional_extensions.push(Box::new(pick));
+        self
+    }
+
+    pub fn api_layer(mut self, name: &str) -> Self {
+        self.api_layers.push(name.into());
+        self
+    }
+
+    pub fn form_factor(mut self, form_factor: xr::FormFactor) -> Self {
+        self.form_factor = form_factor;
+        self
+    }
+
+    /// Blend modes in order of preference. The first one the runtime supports is used; if none
+    /// are, or the list is empty, the runtime's own first choice is taken.
+    pub fn blend_modes(mut self, modes: &[xr::EnvironmentBlendMode]) -> Self {
+        self.blend_modes = modes.to_vec();
+        self
+    }
+
+    pub fn vulkan_version(mut self, version: xr::Version) -> Self {
+        self.vulkan_version = version;
+        self
+    }
+
+    /// Swapchain format, depth, view configuration and frames in flight for the renderer.
+    pub fn render_config(mut self, config: RenderConfig) -> Self {
+        self.render_config = config;
+        self
+    }
+
+    /// The Vulkan version in the packed form `vk::ApplicationInfo` expects.
+    pub(crate) fn vk_target_version(&self) -> u32 {
+        vk::make_api_version(
+            0,
+            self.vulkan_version.major() as u32,
+            self.vulkan_version.minor() as u32,
+            self.vulkan_version.patch(),
+        )
+    }
+
+    pub fn build(mut self) -> Result<KaleidoContext> {
+        let (instance, system, environment_blend_mode) = init_openxr(&self)?;
+
+        // Depth submission is best-effort: without the extension we simply don't do it.
+        if self.render_config.submit_depth.is_some() {
+            if instance.exts().khr_composition_layer_depth.is_some() {
+                self.render_config.depth = Some(DepthSource::XrSwapchain);
+            } else {
+                log::info!("XR_KHR_composition_layer_depth unavailable, not submitting depth");
+                self.render_config.submit_depth = None;
+            }
+        }
+        let device = init_vulkan(&instance, system, &self)?;
+
+        let (session, frame_waiter, frame_stream) = create_session(&instance, system, &device)?;
+
+        self.render_config.color_format =
+            negotiate_color_format(&session, &self.render_config.color_formats)?;
+        self.render_config.samples = clamp_sample_count(&device, &self.render_config);
+        // A runtime depth swapchain is single-sampled and can't back a multisampled depth
+        // attachment, so MSAA falls back to owned depth and stops submitting it.
+        if self.render_config.multisampled()
+            && self.render_config.depth == Some(DepthSource::XrSwapchain)
+        {
+            log::info!("depth submission is unavailable with MSAA, using owned depth");
+            self.render_config.depth = Some(DepthSource::Owned);
+            self.render_config.submit_depth = None;
+        }
+        if self.render_config.depth.is_some() {
+            self.render_config.depth_format =
+                negotiate_depth_format(&device, &session, &self.render_config)?;
+        }
+
+        Ok(KaleidoContext {
+            frame_stream,
+            frame_waiter,
+            session,
+            device,
+            system,
+            environment_blend_mode,
+            render_config: self.render_config,
+            instance,
+        })
+    }
+}
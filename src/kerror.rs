@@ -0,0 +1,148 @@
+use std::fmt;
+
+use ash::vk;
+use openxr as xr;
+
+/// Everything that can go wrong while bringing up or driving kaleido.
+#[derive(Debug)]
+pub enum KaleidoError {
+    /// The OpenXR loader or the Vulkan library couldn't be loaded.
+    Loader(String),
+    /// The active OpenXR runtime doesn't provide an extension we can't run without.
+    MissingExtension(&'static str),
+    /// An API layer the application asked for isn't installed.
+    MissingApiLayer(String),
+    /// The OpenXR runtime can't work with the Vulkan version we target.
+    UnsupportedVulkanVersion {
+        requested: xr::Version,
+        min: xr::Version,
+        max: xr::Version,
+    },
+    /// The runtime reported no environment blend modes for the view configuration.
+    NoEnvironmentBlendMode,
+    /// The Vulkan device the runtime picked has no graphics queue.
+    NoGraphicsQueue,
+    /// None of the preferred swapchain formats are supported by the runtime.
+    NoSupportedSwapchainFormat,
+    /// None of the preferred depth formats are usable as a depth attachment.
+    NoSupportedDepthFormat,
+    /// No device memory type satisfies an allocation's requirements.
+    NoSuitableMemoryType,
+    /// A write of `len` bytes didn't fit in a buffer of `size` bytes.
+    BufferOverflow { size: u64, len: u64 },
+    /// The color and depth swapchains handed out different image indices.
+    SwapchainOutOfSync,
+    /// The runtime reported a different number of views than we render.
+    ViewCountMismatch { expected: u32, found: u32 },
+    /// A renderer draws at most `max` views per multiview pass, but the config has more.
+    TooManyViews { max: u32, found: u32 },
+    /// The runtime moved the session between two states the OpenXR spec doesn't connect.
+    InvalidSessionTransition {
+        from: xr::SessionState,
+        to: xr::SessionState,
+    },
+    /// An action manifest couldn't be read, or failed validation. The message says where.
+    InvalidManifest(String),
+    /// A SPIR-V blob couldn't be read.
+    InvalidShader(std::io::Error),
+    /// An OpenXR call failed.
+    Xr(xr::sys::Result),
+    /// A Vulkan call failed.
+    Vulkan(vk::Result),
+}
+
+pub type Result<T> = std::result::Result<T, KaleidoError>;
+
+impl fmt::Display for KaleidoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KaleidoError::Loader(msg) => write!(f, "failed to load runtime library: {}", msg),
+            KaleidoError::MissingExtension(name) => {
+                write!(
+                    f,
+                    "OpenXR runtime does not support required extension {}",
+                    name
+                )
+            }
+            KaleidoError::MissingApiLayer(name) => {
+                write!(f, "OpenXR API layer {} is not available", name)
+            }
+            KaleidoError::UnsupportedVulkanVersion {
+                requested,
+                min,
+                max,
+            } => write!(
+                f,
+                "OpenXR runtime requires Vulkan version > {}, < {}.0.0 but {} was requested",
+                min,
+                max.major() + 1,
+                requested
+            ),
+            KaleidoError::NoEnvironmentBlendMode => {
+                write!(f, "OpenXR runtime reported no environment blend modes")
+            }
+            KaleidoError::NoGraphicsQueue => write!(f, "Vulkan device has no graphics queue"),
+            KaleidoError::NoSupportedSwapchainFormat => {
+                write!(
+                    f,
+                    "OpenXR runtime supports none of the requested swapchain formats"
+                )
+            }
+            KaleidoError::NoSupportedDepthFormat => {
+                write!(f, "none of the requested depth formats are supported")
+            }
+            KaleidoError::NoSuitableMemoryType => {
+                write!(f, "no Vulkan memory type satisfies the allocation")
+            }
+            KaleidoError::BufferOverflow { size, len } => {
+                write!(f, "can't write {} bytes to a buffer of {} bytes", len, size)
+            }
+            KaleidoError::SwapchainOutOfSync => {
+                write!(f, "color and depth swapchains acquired different images")
+            }
+            KaleidoError::ViewCountMismatch { expected, found } => write!(
+                f,
+                "expected {} views from the OpenXR runtime, found {}",
+                expected, found
+            ),
+            KaleidoError::TooManyViews { max, found } => write!(
+                f,
+                "can draw at most {} views in one multiview pass, the config has {}",
+                max, found
+            ),
+            KaleidoError::InvalidSessionTransition { from, to } => {
+                write!(
+                    f,
+                    "invalid session state transition from {:?} to {:?}",
+                    from, to
+                )
+            }
+            KaleidoError::InvalidManifest(msg) => write!(f, "invalid action manifest: {}", msg),
+            KaleidoError::InvalidShader(e) => write!(f, "invalid SPIR-V: {}", e),
+            KaleidoError::Xr(e) => write!(f, "OpenXR error: {}", e),
+            KaleidoError::Vulkan(e) => write!(f, "Vulkan error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KaleidoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KaleidoError::InvalidShader(e) => Some(e),
+            KaleidoError::Vulkan(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<xr::sys::Result> for KaleidoError {
+    fn from(e: xr::sys::Result) -> Self {
+        KaleidoError::Xr(e)
+    }
+}
+
+impl From<vk::Result> for KaleidoError {
+    fn from(e: vk::Result) -> Self {
+        KaleidoError::Vulkan(e)
+    }
+}
@@ -0,0 +1,84 @@
+use openxr as xr;
+
+/// Generates `EventBus` with one typed subscription method per listed `xr::Event` variant.
+macro_rules! event_bus {
+    ($($(#[$doc:meta])* $variant:ident => $method:ident,)*) => {
+        /// Hands the OpenXR events kaleido polls to the handlers subscribed to their type.
+        ///
+        /// Events nobody subscribed to are logged through the `log` crate instead.
+        #[derive(Default)]
+        pub struct EventBus {
+            $($method: Vec<Box<dyn FnMut(xr::$variant<'_>)>>,)*
+        }
+
+        impl EventBus {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            $(
+                $(#[$doc])*
+                pub fn $method(&mut self, handler: impl FnMut(xr::$variant<'_>) + 'static) -> &mut Self {
+                    self.$method.push(Box::new(handler));
+                    self
+                }
+            )*
+
+            /// Runs the handlers subscribed to `event`'s type. Returns false if there were none.
+            pub fn dispatch(&mut self, event: xr::Event<'_>) -> bool {
+                match event {
+                    $(xr::Event::$variant(e) if !self.$method.is_empty() => {
+                        self.$method.iter_mut().for_each(|handler| handler(e));
+                        true
+                    })*
+                    _ => false,
+                }
+            }
+        }
+
+        /// Name of `event`'s type, for logging.
+        pub fn event_name(event: &xr::Event<'_>) -> &'static str {
+            match event {
+                $(xr::Event::$variant(_) => stringify!($variant),)*
+                _ => "extension",
+            }
+        }
+    };
+}
+
+event_bus! {
+    /// The runtime's event queue overflowed and events were dropped.
+    EventsLost => on_events_lost,
+    /// The instance is about to be lost. `kaleido::run` tears everything down after handlers ran.
+    InstanceLossPending => on_instance_loss_pending,
+    /// The session moved to a new state. `kaleido::run` already begins and ends the session and
+    /// tracks it in `SessionLifecycle`; handlers see the event first.
+    SessionStateChanged => on_session_state_changed,
+    /// A reference space's origin is about to move, e.g. after the user recentered.
+    ReferenceSpaceChangePending => on_reference_space_change_pending,
+    /// The interaction profile bound to a top-level user path changed, e.g. a controller was
+    /// swapped.
+    InteractionProfileChanged => on_interaction_profile_changed,
+    /// A view's visibility mask changed (XR_KHR_visibility_mask).
+    VisibilityMaskChangedKHR => on_visibility_mask_changed,
+    /// A performance level notification (XR_EXT_performance_settings).
+    PerfSettingsEXT => on_perf_settings,
+    /// The display refresh rate changed (XR_FB_display_refresh_rate).
+    DisplayRefreshRateChangedFB => on_display_refresh_rate_changed,
+    /// The user put the headset on or took it off (XR_EXT_user_presence).
+    UserPresenceChangedEXT => on_user_presence_changed,
+    /// The main session became visible or hidden (XR_EXTX_overlay).
+    MainSessionVisibilityChangedEXTX => on_main_session_visibility_changed,
+    /// A passthrough layer changed state (XR_FB_passthrough).
+    PassthroughStateChangedFB => on_passthrough_state_changed,
+    /// A Vive tracker was connected (XR_HTCX_vive_tracker_interaction).
+    ViveTrackerConnectedHTCX => on_vive_tracker_connected,
+}
+
+/// Logs an event no handler was subscribed to.
+pub fn log_unhandled(event: &xr::Event<'_>) {
+    match event {
+        xr::Event::EventsLost(e) => log::warn!("lost {} events", e.lost_event_count()),
+        _ => log::debug!("unhandled {} event", event_name(event)),
+    }
+}
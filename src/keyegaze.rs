@@ -0,0 +1,213 @@
+use std::ptr;
+
+use openxr as xr;
+
+use crate::kerror::Result;
+use crate::kmath::{add, dot, forward, normalize, scale, sub};
+
+const PROFILE: &str = "/interaction_profiles/ext/eye_gaze_interaction";
+const GAZE_POSE: &str = "/user/eyes_ext/input/gaze_ext/pose";
+
+/// A half-line in some space, starting at `origin` and running along the unit vector
+/// `direction`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: xr::Vector3f,
+    pub direction: xr::Vector3f,
+}
+
+impl Ray {
+    /// The ray from `pose`'s position along its forward (-Z) axis.
+    pub fn from_pose(pose: xr::Posef) -> Self {
+        Self {
+            origin: pose.position,
+            direction: normalize(forward(pose)),
+        }
+    }
+
+    /// The point `distance` meters along the ray.
+    pub fn at(&self, distance: f32) -> xr::Vector3f {
+        add(self.origin, scale(self.direction, distance))
+    }
+}
+
+/// The shape of something gaze can hit, in the same space as the rays tested against it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collider {
+    Sphere {
+        center: xr::Vector3f,
+        radius: f32,
+    },
+    /// An axis-aligned box between two opposite corners.
+    Box {
+        min: xr::Vector3f,
+        max: xr::Vector3f,
+    },
+}
+
+impl Collider {
+    /// How far along `ray` it first enters the shape, or 0 if the ray starts inside it.
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        match *self {
+            Collider::Sphere { center, radius } => {
+                let to_center = sub(center, ray.origin);
+                let along = dot(to_center, ray.direction);
+                let off_axis = dot(to_center, to_center) - along * along;
+                let half_chord = (radius * radius - off_axis).sqrt();
+                if half_chord.is_nan() {
+                    return None;
+                }
+                let (near, far) = (along - half_chord, along + half_chord);
+                (far >= 0.0).then_some(near.max(0.0))
+            }
+            Collider::Box { min, max } => {
+                let (mut near, mut far) = (0.0f32, f32::INFINITY);
+                for (origin, direction, min, max) in [
+                    (ray.origin.x, ray.direction.x, min.x, max.x),
+                    (ray.origin.y, ray.direction.y, min.y, max.y),
+                    (ray.origin.z, ray.direction.z, min.z, max.z),
+                ] {
+                    if direction.abs() < f32::EPSILON {
+                        if origin < min || origin > max {
+                            return None;
+                        }
+                        continue;
+                    }
+                    let (a, b) = ((min - origin) / direction, (max - origin) / direction);
+                    near = near.max(a.min(b));
+                    far = far.min(a.max(b));
+                }
+                (near <= far).then_some(near)
+            }
+        }
+    }
+}
+
+/// The closest target a ray hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GazeHit {
+    pub id: u64,
+    pub distance: f32,
+    pub point: xr::Vector3f,
+}
+
+/// The objects gaze can select, by app-chosen id.
+#[derive(Debug, Clone, Default)]
+pub struct GazeTargets {
+    targets: Vec<(u64, Collider)>,
+}
+
+impl GazeTargets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a target, or moves the one already registered under `id`.
+    pub fn insert(&mut self, id: u64, collider: Collider) {
+        match self.targets.iter_mut().find(|(target, _)| *target == id) {
+            Some(target) => target.1 = collider,
+            None => self.targets.push((id, collider)),
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.targets.retain(|(target, _)| *target != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    /// The nearest target `ray` hits, if any.
+    pub fn hit(&self, ray: &Ray) -> Option<GazeHit> {
+        self.targets
+            .iter()
+            .filter_map(|(id, collider)| Some((*id, collider.intersect(ray)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, distance)| GazeHit {
+                id,
+                distance,
+                point: ray.at(distance),
+            })
+    }
+}
+
+/// Where the user is looking, through XR_EXT_eye_gaze_interaction.
+///
+/// The extension is optional: enable it with
+/// `|available, enabled| enabled.ext_eye_gaze_interaction = available.ext_eye_gaze_interaction`
+/// in `KaleidoContextBuilder::optional_extensions`. Its action set isn't attached or synced here;
+/// pass `set` to `ControllerInput::with_action_sets` or `ActionManifest::create_with_action_sets`,
+/// whose `sync` then updates it, or attach and sync it together with the app's own sets. Syncing
+/// it on its own would deactivate the others.
+pub struct EyeGaze {
+    pub set: xr::ActionSet,
+    pub pose: xr::Action<xr::Posef>,
+    space: xr::Space,
+}
+
+impl EyeGaze {
+    /// Declares the gaze action and suggests its binding, or returns `None` when the extension
+    /// isn't enabled or the system has no eye tracker.
+    pub fn new(
+        instance: &xr::Instance,
+        system: xr::SystemId,
+        session: &xr::Session<xr::Vulkan>,
+    ) -> Result<Option<Self>> {
+        if instance.exts().ext_eye_gaze_interaction.is_none() {
+            log::info!("XR_EXT_eye_gaze_interaction not enabled, no gaze input");
+            return Ok(None);
+        }
+        if !supports_eye_gaze(instance, system)? {
+            log::info!("system can't track eye gaze, no gaze input");
+            return Ok(None);
+        }
+
+        let set = instance.create_action_set("gaze", "Gaze", 0)?;
+        let pose = set.create_action::<xr::Posef>("gaze_pose", "Gaze Pose", &[])?;
+        instance.suggest_interaction_profile_bindings(
+            instance.string_to_path(PROFILE)?,
+            &[xr::Binding::new(&pose, instance.string_to_path(GAZE_POSE)?)],
+        )?;
+        let space = pose.create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)?;
+        Ok(Some(Self { set, pose, space }))
+    }
+
+    /// The gaze ray in `space` at `time`, or `None` while the eyes aren't tracked, e.g. during
+    /// a blink or without input focus.
+    pub fn ray(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        space: &xr::Space,
+        time: xr::Time,
+    ) -> Result<Option<Ray>> {
+        if !self.pose.is_active(session, xr::Path::NULL)? {
+            return Ok(None);
+        }
+        let location = self.space.locate(space, time)?;
+        let valid =
+            xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID;
+        Ok(location
+            .location_flags
+            .contains(valid)
+            .then(|| Ray::from_pose(location.pose)))
+    }
+}
+
+/// Whether the system can track eye gaze. openxrs only has this query for hand tracking, so it's
+/// spelled out against the raw function pointer.
+fn supports_eye_gaze(instance: &xr::Instance, system: xr::SystemId) -> Result<bool> {
+    unsafe {
+        let mut gaze = xr::sys::SystemEyeGazeInteractionPropertiesEXT::out(ptr::null_mut());
+        let mut properties = xr::sys::SystemProperties::out(gaze.as_mut_ptr() as *mut _);
+        let result = (instance.fp().get_system_properties)(
+            instance.as_raw(),
+            system,
+            properties.as_mut_ptr(),
+        );
+        if result.into_raw() < 0 {
+            return Err(result.into());
+        }
+        Ok(gaze.assume_init().supports_eye_gaze_interaction.into())
+    }
+}
@@ -0,0 +1,453 @@
+use openxr as xr;
+
+use crate::khands::HandJoints;
+use crate::kmath::{distance, length, sub};
+
+/// The joints whose straightness tells how curled the thumb is, from its base to its tip.
+const THUMB: [xr::HandJoint; 4] = [
+    xr::HandJoint::THUMB_METACARPAL,
+    xr::HandJoint::THUMB_PROXIMAL,
+    xr::HandJoint::THUMB_DISTAL,
+    xr::HandJoint::THUMB_TIP,
+];
+
+/// The same for the index, middle, ring and little fingers, from the first knuckle on.
+const FINGERS: [[xr::HandJoint; 4]; 4] = [
+    [
+        xr::HandJoint::INDEX_PROXIMAL,
+        xr::HandJoint::INDEX_INTERMEDIATE,
+        xr::HandJoint::INDEX_DISTAL,
+        xr::HandJoint::INDEX_TIP,
+    ],
+    [
+        xr::HandJoint::MIDDLE_PROXIMAL,
+        xr::HandJoint::MIDDLE_INTERMEDIATE,
+        xr::HandJoint::MIDDLE_DISTAL,
+        xr::HandJoint::MIDDLE_TIP,
+    ],
+    [
+        xr::HandJoint::RING_PROXIMAL,
+        xr::HandJoint::RING_INTERMEDIATE,
+        xr::HandJoint::RING_DISTAL,
+        xr::HandJoint::RING_TIP,
+    ],
+    [
+        xr::HandJoint::LITTLE_PROXIMAL,
+        xr::HandJoint::LITTLE_INTERMEDIATE,
+        xr::HandJoint::LITTLE_DISTAL,
+        xr::HandJoint::LITTLE_TIP,
+    ],
+];
+
+/// A hand pose [`GestureRecognizer`] knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Thumb and index fingertips touching.
+    Pinch,
+    /// All four fingers curled into a fist.
+    Grab,
+    /// Index finger out, the other three curled.
+    Point,
+    /// Fist with the thumb out and pointing up.
+    ThumbsUp,
+    /// Every finger and the thumb stretched out.
+    OpenPalm,
+}
+
+impl Gesture {
+    pub const ALL: [Gesture; 5] = [
+        Gesture::Pinch,
+        Gesture::Grab,
+        Gesture::Point,
+        Gesture::ThumbsUp,
+        Gesture::OpenPalm,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureEvent {
+    Started(Gesture),
+    Ended(Gesture),
+}
+
+/// Tuning for [`GestureRecognizer`]. Scores run from 0 to 1; a gesture starts once its score
+/// reaches `enter` and only ends when it drops below `exit`, so a hand hovering around one
+/// threshold doesn't flicker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    pub enter: f32,
+    pub exit: f32,
+    /// Gap between the thumb and index fingertip surfaces, in meters, at which pinch strength
+    /// reaches 1.
+    pub pinch_closed: f32,
+    /// Gap at which pinch strength drops to 0.
+    pub pinch_open: f32,
+    /// How straight a curled finger still is: the distance from its first knuckle to its tip
+    /// over the length of its bones. A fully stretched finger is at 1.
+    pub curled_ratio: f32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            enter: 0.8,
+            exit: 0.6,
+            pinch_closed: 0.005,
+            pinch_open: 0.05,
+            curled_ratio: 0.55,
+        }
+    }
+}
+
+/// Recognizes gestures from one hand's joints, frame by frame.
+///
+/// The recognizer only looks at the [`HandJoints`] it is fed, so it works the same on live data
+/// from `HandTracking::locate` and on recorded joint streams. Keep one per hand. Joints are
+/// expected in a space whose +Y is up, which every reference space but VIEW is.
+#[derive(Debug, Clone)]
+pub struct GestureRecognizer {
+    pub config: GestureConfig,
+    scores: [f32; 5],
+    active: [bool; 5],
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(GestureConfig::default())
+    }
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            scores: [0.0; 5],
+            active: [false; 5],
+        }
+    }
+
+    /// Scores every gesture against `joints` and reports the ones that started or ended. Pass
+    /// `None` while the hand isn't tracked; that ends every active gesture.
+    pub fn update(&mut self, joints: Option<&HandJoints>) -> Vec<GestureEvent> {
+        self.scores = match joints.filter(|joints| joints.all_valid()) {
+            Some(joints) => self.score_all(joints),
+            None => [0.0; 5],
+        };
+
+        let mut events = Vec::new();
+        for gesture in Gesture::ALL {
+            let score = self.scores[gesture.index()];
+            let active = &mut self.active[gesture.index()];
+            if !*active && score >= self.config.enter {
+                *active = true;
+                events.push(GestureEvent::Started(gesture));
+            } else if *active && score < self.config.exit {
+                *active = false;
+                events.push(GestureEvent::Ended(gesture));
+            }
+        }
+        events
+    }
+
+    pub fn is_active(&self, gesture: Gesture) -> bool {
+        self.active[gesture.index()]
+    }
+
+    /// How strongly the hand made `gesture` in the last update, from 0 to 1.
+    pub fn score(&self, gesture: Gesture) -> f32 {
+        self.scores[gesture.index()]
+    }
+
+    /// How close the thumb and index fingertips are, from 0 (apart) to 1 (touching).
+    pub fn pinch_strength(&self) -> f32 {
+        self.score(Gesture::Pinch)
+    }
+
+    fn score_all(&self, joints: &HandJoints) -> [f32; 5] {
+        let thumb = self.curl(joints, THUMB);
+        let fingers = FINGERS.map(|finger| self.curl(joints, finger));
+        let [index, middle, ring, little] = fingers;
+        let fist = middle.min(ring).min(little);
+
+        let (thumb_tip, index_tip) = (xr::HandJoint::THUMB_TIP, xr::HandJoint::INDEX_TIP);
+        let gap = distance(joints.position(thumb_tip), joints.position(index_tip))
+            - joints.radius(thumb_tip)
+            - joints.radius(index_tip);
+        let span = self.config.pinch_open - self.config.pinch_closed;
+        let pinch = 1.0 - ((gap - self.config.pinch_closed) / span).clamp(0.0, 1.0);
+
+        // How far the thumb points straight up, 0 once it's horizontal.
+        let thumb_direction = sub(
+            joints.position(xr::HandJoint::THUMB_TIP),
+            joints.position(xr::HandJoint::THUMB_PROXIMAL),
+        );
+        let thumb_up = (thumb_direction.y / length(thumb_direction).max(f32::EPSILON)).max(0.0);
+
+        let mut scores = [0.0; 5];
+        scores[Gesture::Pinch.index()] = pinch;
+        scores[Gesture::Grab.index()] = fist.min(index);
+        scores[Gesture::Point.index()] = (1.0 - index).min(fist);
+        scores[Gesture::ThumbsUp.index()] = (1.0 - thumb).min(fist.min(index)).min(thumb_up);
+        scores[Gesture::OpenPalm.index()] = 1.0 - fingers.into_iter().fold(thumb, f32::max);
+        scores
+    }
+
+    /// How curled the finger through `chain` is, from 0 (straight) to 1 (curled).
+    fn curl(&self, joints: &HandJoints, chain: [xr::HandJoint; 4]) -> f32 {
+        let [a, b, c, d] = chain.map(|joint| joints.position(joint));
+        let bones = distance(a, b) + distance(b, c) + distance(c, d);
+        let straightness = distance(a, d) / bones.max(f32::EPSILON);
+        ((1.0 - straightness) / (1.0 - self.config.curled_ratio)).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::khands::JOINT_COUNT;
+    use crate::kmath::{add, scale, vec3};
+
+    use GestureEvent::{Ended, Started};
+
+    /// Length of every finger bone in the synthetic hands, in meters.
+    const BONE: f32 = 0.03;
+    const THUMB_BASE: xr::Vector3f = xr::Vector3f {
+        x: 0.04,
+        y: 0.0,
+        z: -0.02,
+    };
+    /// Knuckles of the index, middle, ring and little fingers, side by side along -X.
+    const KNUCKLES: [f32; 4] = [0.02, 0.0, -0.02, -0.04];
+
+    fn knuckle(finger: usize) -> xr::Vector3f {
+        vec3(KNUCKLES[finger], 0.0, -0.08)
+    }
+
+    /// A chain of three bones in a straight line from `base` along the unit vector `direction`.
+    fn straight(base: xr::Vector3f, direction: xr::Vector3f) -> [xr::Vector3f; 4] {
+        [0.0, 1.0, 2.0, 3.0].map(|i| add(base, scale(direction, BONE * i)))
+    }
+
+    /// A chain folded forward, down and back under its base, like a finger in a fist.
+    fn curled(base: xr::Vector3f) -> [xr::Vector3f; 4] {
+        [
+            base,
+            add(base, vec3(0.0, 0.0, -BONE)),
+            add(base, vec3(0.0, -BONE, -BONE)),
+            add(base, vec3(0.0, -BONE, 0.0)),
+        ]
+    }
+
+    /// A chain bent about halfway between `straight` and `curled`.
+    fn half_curled(base: xr::Vector3f) -> [xr::Vector3f; 4] {
+        [
+            base,
+            add(base, vec3(0.0, 0.0, -BONE)),
+            add(base, vec3(0.0, -0.02, -0.05)),
+            add(base, vec3(0.0, -0.05, -0.05)),
+        ]
+    }
+
+    /// Every joint valid, with the thumb and finger chains at the given positions and everything
+    /// else at the origin.
+    fn hand(thumb: [xr::Vector3f; 4], fingers: [[xr::Vector3f; 4]; 4]) -> HandJoints {
+        let mut joints = [xr::HandJointLocation {
+            location_flags: xr::SpaceLocationFlags::POSITION_VALID
+                | xr::SpaceLocationFlags::ORIENTATION_VALID
+                | xr::SpaceLocationFlags::POSITION_TRACKED
+                | xr::SpaceLocationFlags::ORIENTATION_TRACKED,
+            pose: xr::Posef::IDENTITY,
+            radius: 0.005,
+        }; JOINT_COUNT];
+        for (chain, positions) in std::iter::once(THUMB)
+            .chain(FINGERS)
+            .zip(std::iter::once(thumb).chain(fingers))
+        {
+            for (joint, position) in chain.into_iter().zip(positions) {
+                joints[joint].pose.position = position;
+            }
+        }
+        HandJoints { joints }
+    }
+
+    fn open_palm() -> HandJoints {
+        hand(
+            straight(THUMB_BASE, vec3(1.0, 0.0, 0.0)),
+            [0, 1, 2, 3].map(|finger| straight(knuckle(finger), vec3(0.0, 0.0, -1.0))),
+        )
+    }
+
+    fn fist() -> HandJoints {
+        hand(
+            curled(THUMB_BASE),
+            [0, 1, 2, 3].map(|finger| curled(knuckle(finger))),
+        )
+    }
+
+    fn point() -> HandJoints {
+        hand(
+            curled(THUMB_BASE),
+            [0, 1, 2, 3].map(|finger| match finger {
+                0 => straight(knuckle(0), vec3(0.0, 0.0, -1.0)),
+                _ => curled(knuckle(finger)),
+            }),
+        )
+    }
+
+    fn thumbs_up() -> HandJoints {
+        hand(
+            straight(THUMB_BASE, vec3(0.0, 1.0, 0.0)),
+            [0, 1, 2, 3].map(|finger| curled(knuckle(finger))),
+        )
+    }
+
+    /// The index finger out, the others half curled, and the thumb tip `gap` meters beside the
+    /// index fingertip, measured between the joint centers.
+    fn pinch(gap: f32) -> HandJoints {
+        let index = straight(knuckle(0), vec3(0.0, 0.0, -1.0));
+        let tip = add(index[3], vec3(gap, 0.0, 0.0));
+        let thumb = [
+            THUMB_BASE,
+            vec3(0.06, 0.0, -0.08),
+            vec3(0.05, 0.0, -0.14),
+            tip,
+        ];
+        hand(
+            thumb,
+            [
+                index,
+                half_curled(knuckle(1)),
+                half_curled(knuckle(2)),
+                half_curled(knuckle(3)),
+            ],
+        )
+    }
+
+    /// The center distance at which `pinch` scores `score` with the default config and the
+    /// 5 mm joint radii.
+    fn pinch_gap(score: f32) -> f32 {
+        let config = GestureConfig::default();
+        let surface_gap =
+            config.pinch_closed + (1.0 - score) * (config.pinch_open - config.pinch_closed);
+        surface_gap + 2.0 * 0.005
+    }
+
+    /// Feeds a recorded stream of frames to a fresh recognizer and collects each frame's events.
+    fn replay(frames: &[Option<HandJoints>]) -> Vec<Vec<GestureEvent>> {
+        let mut recognizer = GestureRecognizer::default();
+        frames
+            .iter()
+            .map(|joints| recognizer.update(joints.as_ref()))
+            .collect()
+    }
+
+    #[test]
+    fn recognizes_each_pose() {
+        for (joints, expected) in [
+            (open_palm(), &[Started(Gesture::OpenPalm)][..]),
+            (fist(), &[Started(Gesture::Grab)][..]),
+            (point(), &[Started(Gesture::Point)][..]),
+            (pinch(0.0), &[Started(Gesture::Pinch)][..]),
+            // A thumbs-up is a fist too, so it grabs as well.
+            (
+                thumbs_up(),
+                &[Started(Gesture::Grab), Started(Gesture::ThumbsUp)][..],
+            ),
+        ] {
+            assert_eq!(replay(&[Some(joints)]), [expected.to_vec()]);
+        }
+    }
+
+    #[test]
+    fn reports_changes_between_poses() {
+        // Events within a frame come in `Gesture::ALL` order.
+        let events = replay(&[
+            Some(open_palm()),
+            Some(open_palm()),
+            Some(fist()),
+            Some(point()),
+            Some(point()),
+            Some(open_palm()),
+        ]);
+        assert_eq!(
+            events,
+            [
+                vec![Started(Gesture::OpenPalm)],
+                vec![],
+                vec![Started(Gesture::Grab), Ended(Gesture::OpenPalm)],
+                vec![Ended(Gesture::Grab), Started(Gesture::Point)],
+                vec![],
+                vec![Ended(Gesture::Point), Started(Gesture::OpenPalm)],
+            ]
+        );
+    }
+
+    #[test]
+    fn pinch_strength_follows_the_fingertip_gap() {
+        let mut recognizer = GestureRecognizer::default();
+        for score in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            recognizer.update(Some(&pinch(pinch_gap(score))));
+            assert!(
+                (recognizer.pinch_strength() - score).abs() < 1e-4,
+                "expected {}, got {}",
+                score,
+                recognizer.pinch_strength()
+            );
+        }
+        recognizer.update(Some(&pinch(0.2)));
+        assert_eq!(recognizer.pinch_strength(), 0.0);
+    }
+
+    #[test]
+    fn hysteresis_keeps_gestures_between_thresholds() {
+        // Between exit (0.6) and enter (0.8), the gesture keeps whatever state it had.
+        let between = Some(pinch(pinch_gap(0.7)));
+        let events = replay(&[
+            between,
+            Some(pinch(pinch_gap(0.85))),
+            between,
+            between,
+            Some(pinch(pinch_gap(0.5))),
+            between,
+        ]);
+        assert_eq!(
+            events,
+            [
+                vec![],
+                vec![Started(Gesture::Pinch)],
+                vec![],
+                vec![],
+                vec![Ended(Gesture::Pinch)],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn losing_the_hand_ends_every_gesture() {
+        let mut recognizer = GestureRecognizer::default();
+        recognizer.update(Some(&thumbs_up()));
+        assert!(recognizer.is_active(Gesture::Grab) && recognizer.is_active(Gesture::ThumbsUp));
+
+        assert_eq!(
+            recognizer.update(None),
+            [Ended(Gesture::Grab), Ended(Gesture::ThumbsUp)]
+        );
+        assert!(Gesture::ALL.iter().all(|g| recognizer.score(*g) == 0.0));
+        assert_eq!(recognizer.update(None), []);
+    }
+
+    #[test]
+    fn invalid_joints_count_as_a_lost_hand() {
+        let mut partial = fist();
+        partial.joints[xr::HandJoint::INDEX_TIP].location_flags = xr::SpaceLocationFlags::EMPTY;
+        assert_eq!(
+            replay(&[Some(fist()), Some(partial)]),
+            [vec![Started(Gesture::Grab)], vec![Ended(Gesture::Grab)]]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use openxr as xr;
+
+use crate::kerror::Result;
+use crate::kinput::{Hand, HandSnapshot};
+
+/// Number of joints XR_EXT_hand_tracking reports per hand.
+pub const JOINT_COUNT: usize = xr::HAND_JOINT_COUNT;
+
+/// Every joint of one hand as of one frame, indexed by `xr::HandJoint`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HandJoints {
+    pub joints: xr::HandJointLocations,
+}
+
+impl HandJoints {
+    pub fn joint(&self, joint: xr::HandJoint) -> &xr::HandJointLocation {
+        &self.joints[joint]
+    }
+
+    pub fn pose(&self, joint: xr::HandJoint) -> xr::Posef {
+        self.joints[joint].pose
+    }
+
+    pub fn position(&self, joint: xr::HandJoint) -> xr::Vector3f {
+        self.joints[joint].pose.position
+    }
+
+    /// Distance from the joint's center to the surface of the skin, in meters.
+    pub fn radius(&self, joint: xr::HandJoint) -> f32 {
+        self.joints[joint].radius
+    }
+
+    /// Whether the runtime knows where the joint is, measured or inferred.
+    pub fn is_valid(&self, joint: xr::HandJoint) -> bool {
+        self.joints[joint].location_flags.contains(
+            xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID,
+        )
+    }
+
+    /// Whether the joint's position was measured rather than inferred this frame.
+    pub fn is_tracked(&self, joint: xr::HandJoint) -> bool {
+        self.joints[joint]
+            .location_flags
+            .contains(xr::SpaceLocationFlags::POSITION_TRACKED)
+    }
+
+    /// Whether every joint is valid.
+    pub fn all_valid(&self) -> bool {
+        (0..JOINT_COUNT as i32).all(|i| self.is_valid(xr::HandJoint::from_raw(i)))
+    }
+}
+
+/// Where a hand is, from the best source available this frame.
+// Lives on the stack for a frame at most, so the joints aren't worth boxing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy)]
+pub enum HandPose {
+    /// Articulated joints from hand tracking.
+    Joints(HandJoints),
+    /// Hand tracking is unavailable or lost the hand; the controller's grip pose stands in.
+    Controller(xr::SpaceLocation),
+    /// Neither source knows where the hand is.
+    Untracked,
+}
+
+impl HandPose {
+    /// The palm pose, or the grip pose when falling back to the controller.
+    pub fn palm(&self) -> Option<xr::Posef> {
+        match self {
+            HandPose::Joints(joints) => Some(joints.pose(xr::HandJoint::PALM)),
+            HandPose::Controller(location) => Some(location.pose),
+            HandPose::Untracked => None,
+        }
+    }
+}
+
+/// Articulated hand tracking through XR_EXT_hand_tracking, with one tracker per hand.
+///
+/// The extension is optional: enable it with
+/// `|available, enabled| enabled.ext_hand_tracking = available.ext_hand_tracking` in
+/// `KaleidoContextBuilder::optional_extensions`. Without it, or on systems that can't track
+/// hands, [`HandTracking::locate`] always returns `None` and [`HandTracking::pose`] falls back to
+/// the controllers. Trackers belong to the session they were created from, so create this after
+/// every session creation and drop it before the session.
+pub struct HandTracking {
+    trackers: Option<[xr::HandTracker; 2]>,
+}
+
+impl HandTracking {
+    pub fn new(
+        instance: &xr::Instance,
+        system: xr::SystemId,
+        session: &xr::Session<xr::Vulkan>,
+    ) -> Result<Self> {
+        if instance.exts().ext_hand_tracking.is_none() {
+            log::info!("XR_EXT_hand_tracking not enabled, hands follow the controllers");
+            return Ok(Self { trackers: None });
+        }
+        if !instance.supports_hand_tracking(system)? {
+            log::info!("system can't track hands, hands follow the controllers");
+            return Ok(Self { trackers: None });
+        }
+        Ok(Self {
+            trackers: Some([
+                session.create_hand_tracker(xr::Hand::LEFT)?,
+                session.create_hand_tracker(xr::Hand::RIGHT)?,
+            ]),
+        })
+    }
+
+    /// Whether joints can be located at all, i.e. the trackers exist.
+    pub fn is_available(&self) -> bool {
+        self.trackers.is_some()
+    }
+
+    /// The joints of `hand` in `space` at `time`, or `None` if the hand isn't tracked right now.
+    pub fn locate(&self, hand: Hand, space: &xr::Space, time: xr::Time) -> Result<Option<HandJoints>> {
+        let Some(trackers) = &self.trackers else {
+            return Ok(None);
+        };
+        Ok(space
+            .locate_hand_joints(&trackers[hand.index()], time)?
+            .map(|joints| HandJoints { joints }))
+    }
+
+    /// The joints of `hand` if they're tracked, otherwise the controller's grip pose from
+    /// `controller`, which should have been taken in the same space at the same time.
+    pub fn pose(
+        &self,
+        hand: Hand,
+        space: &xr::Space,
+        time: xr::Time,
+        controller: &HandSnapshot,
+    ) -> Result<HandPose> {
+        if let Some(joints) = self.locate(hand, space, time)? {
+            return Ok(HandPose::Joints(joints));
+        }
+        let grip = controller.grip;
+        Ok(
+            if grip
+                .location_flags
+                .contains(xr::SpaceLocationFlags::POSITION_VALID)
+            {
+                HandPose::Controller(grip)
+            } else {
+                HandPose::Untracked
+            },
+        )
+    }
+}
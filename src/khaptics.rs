@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use openxr as xr;
+
+use crate::kerror::Result;
+use crate::kinput::Hand;
+
+/// Vibrates the device bound to `action` for `subaction_path`, replacing whatever it was playing.
+///
+/// `amplitude` is clamped to 0..1 and `frequency` is in Hz, with `xr::FREQUENCY_UNSPECIFIED`
+/// leaving it to the runtime. A zero `duration` asks for the shortest pulse the device can do.
+pub fn vibrate(
+    session: &xr::Session<xr::Vulkan>,
+    action: &xr::Action<xr::Haptic>,
+    subaction_path: xr::Path,
+    amplitude: f32,
+    frequency: f32,
+    duration: Duration,
+) -> Result<()> {
+    let duration = if duration.is_zero() {
+        xr::Duration::MIN_HAPTIC
+    } else {
+        xr::Duration::from_nanos(duration.as_nanos().min(i64::MAX as u128) as i64)
+    };
+    let event = xr::HapticVibration::new()
+        .amplitude(amplitude.clamp(0.0, 1.0))
+        .frequency(frequency)
+        .duration(duration);
+    Ok(action.apply_feedback(session, subaction_path, &event)?)
+}
+
+/// Stops any vibration `action` is playing for `subaction_path`.
+pub fn stop(
+    session: &xr::Session<xr::Vulkan>,
+    action: &xr::Action<xr::Haptic>,
+    subaction_path: xr::Path,
+) -> Result<()> {
+    Ok(action.stop_feedback(session, subaction_path)?)
+}
+
+/// One vibration of a [`HapticPattern`], followed by a pause before the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pulse {
+    pub amplitude: f32,
+    /// In Hz, or `xr::FREQUENCY_UNSPECIFIED`.
+    pub frequency: f32,
+    pub duration: Duration,
+    pub pause: Duration,
+}
+
+/// A sequence of pulses played by [`HapticSequencer`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HapticPattern {
+    /// Wait before the first pulse of every pass.
+    pub delay: Duration,
+    pub pulses: Vec<Pulse>,
+    /// How many more times the pulses are played after the first.
+    pub repeats: u32,
+}
+
+impl HapticPattern {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a pulse at the runtime's default frequency.
+    pub fn pulse(mut self, amplitude: f32, duration: Duration) -> Self {
+        self.pulses.push(Pulse {
+            amplitude,
+            frequency: xr::FREQUENCY_UNSPECIFIED,
+            duration,
+            pause: Duration::ZERO,
+        });
+        self
+    }
+
+    /// Waits `pause` after the last pulse before playing the next one, or before the first
+    /// pulse if there's none yet.
+    pub fn pause(mut self, pause: Duration) -> Self {
+        match self.pulses.last_mut() {
+            Some(last) => last.pause += pause,
+            None => self.delay += pause,
+        }
+        self
+    }
+
+    pub fn repeat(mut self, repeats: u32) -> Self {
+        self.repeats = repeats;
+        self
+    }
+
+    /// A single short tap, e.g. for hovering a button.
+    pub fn tick() -> Self {
+        Self::new().pulse(0.3, Duration::from_millis(10))
+    }
+
+    /// Two firm taps, e.g. for confirming a selection.
+    pub fn double_click() -> Self {
+        Self::new()
+            .pulse(0.8, Duration::from_millis(30))
+            .pause(Duration::from_millis(60))
+            .pulse(0.8, Duration::from_millis(30))
+    }
+
+    /// How long one pass over the pulses takes, pauses included.
+    pub fn length(&self) -> Duration {
+        self.delay
+            + self
+                .pulses
+                .iter()
+                .map(|p| p.duration + p.pause)
+                .sum::<Duration>()
+    }
+}
+
+struct Playback {
+    pattern: HapticPattern,
+    next: usize,
+    repeats_left: u32,
+    /// When the next pulse is due; `None` until the first `update` after `play`.
+    due: Option<xr::Time>,
+}
+
+/// Plays [`HapticPattern`]s on a per-hand haptic action, one per hand.
+///
+/// Pulses are started from [`HapticSequencer::update`], so their timing is only as fine as the
+/// frame rate. Call it once per frame with the frame's predicted display time.
+pub struct HapticSequencer {
+    hands: [xr::Path; 2],
+    playing: [Option<Playback>; 2],
+}
+
+impl HapticSequencer {
+    pub fn new(instance: &xr::Instance) -> Result<Self> {
+        Ok(Self {
+            hands: [
+                instance.string_to_path(Hand::Left.path())?,
+                instance.string_to_path(Hand::Right.path())?,
+            ],
+            playing: [None, None],
+        })
+    }
+
+    /// Starts `pattern` on `hand` with the next `update`, replacing what the hand was playing.
+    /// A pattern without pulses just stops the hand's pattern, whatever its delay.
+    pub fn play(&mut self, hand: Hand, pattern: HapticPattern) {
+        self.playing[hand.index()] = (!pattern.pulses.is_empty()).then_some(Playback {
+            repeats_left: pattern.repeats,
+            pattern,
+            next: 0,
+            due: None,
+        });
+    }
+
+    pub fn is_playing(&self, hand: Hand) -> bool {
+        self.playing[hand.index()].is_some()
+    }
+
+    /// Drops the pattern playing on `hand` and stops its current pulse.
+    pub fn cancel(
+        &mut self,
+        session: &xr::Session<xr::Vulkan>,
+        action: &xr::Action<xr::Haptic>,
+        hand: Hand,
+    ) -> Result<()> {
+        if self.playing[hand.index()].take().is_some() {
+            stop(session, action, self.hands[hand.index()])?;
+        }
+        Ok(())
+    }
+
+    /// Starts the pulses that are due at `time` on `action`.
+    pub fn update(
+        &mut self,
+        session: &xr::Session<xr::Vulkan>,
+        action: &xr::Action<xr::Haptic>,
+        time: xr::Time,
+    ) -> Result<()> {
+        for (hand, pulse) in self.poll(time) {
+            let path = self.hands[hand.index()];
+            vibrate(
+                session,
+                action,
+                path,
+                pulse.amplitude,
+                pulse.frequency,
+                pulse.duration,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Advances every hand's pattern to `time` and returns the pulses to start, at most one per
+    /// hand.
+    fn poll(&mut self, time: xr::Time) -> Vec<(Hand, Pulse)> {
+        let mut started = Vec::new();
+        for (hand, playing) in Hand::BOTH.into_iter().zip(&mut self.playing) {
+            let Some(playback) = playing else {
+                continue;
+            };
+            let mut due = *playback
+                .due
+                .get_or_insert_with(|| later(time, playback.pattern.delay));
+            if time.as_nanos() < due.as_nanos() {
+                continue;
+            }
+            if playback.next == playback.pattern.pulses.len() {
+                // The last pulse and its pause are over.
+                if playback.repeats_left == 0 {
+                    *playing = None;
+                    continue;
+                }
+                playback.repeats_left -= 1;
+                playback.next = 0;
+                due = later(due, playback.pattern.delay);
+                playback.due = Some(due);
+                if time.as_nanos() < due.as_nanos() {
+                    continue;
+                }
+            }
+
+            let pulse = playback.pattern.pulses[playback.next];
+            started.push((hand, pulse));
+            playback.next += 1;
+            // Schedule from when the pulse was due rather than when this frame came, so late
+            // frames don't stretch the pattern.
+            playback.due = Some(later(due, pulse.duration + pulse.pause));
+        }
+        started
+    }
+}
+
+fn later(time: xr::Time, by: Duration) -> xr::Time {
+    xr::Time::from_nanos(time.as_nanos() + by.as_nanos() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequencer() -> HapticSequencer {
+        HapticSequencer {
+            hands: [xr::Path::NULL; 2],
+            playing: [None, None],
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// The time `millis` after an arbitrary start.
+    fn at(millis: i64) -> xr::Time {
+        xr::Time::from_nanos(1_000_000_000 + millis * 1_000_000)
+    }
+
+    /// Polls at each of `times` and returns the amplitudes of the pulses started on `hand`.
+    fn amplitudes(sequencer: &mut HapticSequencer, hand: Hand, times: &[i64]) -> Vec<Option<f32>> {
+        times
+            .iter()
+            .map(|&time| {
+                sequencer
+                    .poll(at(time))
+                    .into_iter()
+                    .find(|(h, _)| *h == hand)
+                    .map(|(_, pulse)| pulse.amplitude)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_pulses_after_their_pauses() {
+        let mut sequencer = sequencer();
+        sequencer.play(Hand::Left, HapticPattern::double_click());
+        assert_eq!(
+            amplitudes(&mut sequencer, Hand::Left, &[0, 30, 89, 90, 119]),
+            [Some(0.8), None, None, Some(0.8), None]
+        );
+        assert!(sequencer.is_playing(Hand::Left));
+        assert_eq!(amplitudes(&mut sequencer, Hand::Left, &[120]), [None]);
+        assert!(!sequencer.is_playing(Hand::Left));
+    }
+
+    #[test]
+    fn late_frames_dont_stretch_the_pattern() {
+        let mut sequencer = sequencer();
+        let pattern = HapticPattern::new()
+            .pulse(0.1, ms(10))
+            .pulse(0.2, ms(10))
+            .pulse(0.3, ms(10));
+        sequencer.play(Hand::Right, pattern);
+        // The second pulse was due at 10 but the frame came at 15; the third is still due at 20.
+        assert_eq!(
+            amplitudes(&mut sequencer, Hand::Right, &[0, 15, 19, 20]),
+            [Some(0.1), Some(0.2), None, Some(0.3)]
+        );
+    }
+
+    #[test]
+    fn repeats_the_pattern() {
+        let mut sequencer = sequencer();
+        sequencer.play(Hand::Left, HapticPattern::tick().repeat(2));
+        assert_eq!(
+            amplitudes(&mut sequencer, Hand::Left, &[0, 5, 10, 20, 30]),
+            [Some(0.3), None, Some(0.3), Some(0.3), None]
+        );
+        assert!(!sequencer.is_playing(Hand::Left));
+    }
+
+    #[test]
+    fn a_leading_pause_delays_every_pass() {
+        let pattern = HapticPattern::new()
+            .pause(ms(50))
+            .pulse(0.5, ms(10))
+            .repeat(1);
+        assert_eq!(pattern.delay, ms(50));
+        assert_eq!(pattern.length(), ms(60));
+
+        let mut sequencer = sequencer();
+        sequencer.play(Hand::Left, pattern);
+        assert_eq!(
+            amplitudes(&mut sequencer, Hand::Left, &[0, 49, 50, 60, 109, 110]),
+            [None, None, Some(0.5), None, None, Some(0.5)]
+        );
+        assert_eq!(amplitudes(&mut sequencer, Hand::Left, &[120]), [None]);
+        assert!(!sequencer.is_playing(Hand::Left));
+    }
+
+    #[test]
+    fn empty_patterns_play_nothing() {
+        let mut sequencer = sequencer();
+        sequencer.play(Hand::Left, HapticPattern::new());
+        assert!(!sequencer.is_playing(Hand::Left));
+        sequencer.play(Hand::Left, HapticPattern::new().pause(ms(100)).repeat(3));
+        assert!(!sequencer.is_playing(Hand::Left));
+        assert!(sequencer.poll(at(0)).is_empty());
+
+        // And replace whatever was playing.
+        sequencer.play(Hand::Left, HapticPattern::tick().repeat(10));
+        sequencer.poll(at(0));
+        sequencer.play(Hand::Left, HapticPattern::new());
+        assert!(!sequencer.is_playing(Hand::Left));
+    }
+
+    #[test]
+    fn hands_play_independently() {
+        let mut sequencer = sequencer();
+        sequencer.play(Hand::Left, HapticPattern::tick());
+        assert_eq!(sequencer.poll(at(0)).len(), 1);
+        sequencer.play(Hand::Right, HapticPattern::double_click());
+        let started = sequencer.poll(at(5));
+        assert_eq!(started.len(), 1);
+        assert_eq!(started[0].0, Hand::Right);
+        assert_eq!(sequencer.poll(at(10)), []);
+        assert!(!sequencer.is_playing(Hand::Left));
+        assert!(sequencer.is_playing(Hand::Right));
+    }
+}
@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use openxr as xr;
+
+use crate::kbindings::{active_profile, Bindings, Control, InteractionProfile};
+use crate::kerror::Result;
+use crate::khaptics;
+
+/// One of the two hands actions can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+impl Hand {
+    pub const BOTH: [Hand; 2] = [Hand::Left, Hand::Right];
+
+    /// The top-level user path of the hand, used as the action's sub-action path.
+    pub fn path(self) -> &'static str {
+        match self {
+            Hand::Left => "/user/hand/left",
+            Hand::Right => "/user/hand/right",
+        }
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// An action set whose actions can all be queried per hand.
+pub struct ActionSet {
+    pub handle: xr::ActionSet,
+    hands: [xr::Path; 2],
+}
+
+impl ActionSet {
+    pub fn new(
+        instance: &xr::Instance,
+        name: &str,
+        localized_name: &str,
+        priority: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            handle: instance.create_action_set(name, localized_name, priority)?,
+            hands: [
+                instance.string_to_path(Hand::Left.path())?,
+                instance.string_to_path(Hand::Right.path())?,
+            ],
+        })
+    }
+
+    /// Creates an action with both hands as sub-action paths. `T` picks the action type: `bool`,
+    /// `f32`, `xr::Vector2f`, `xr::Posef` or `xr::Haptic`.
+    pub fn action<T: xr::ActionTy>(&self, name: &str, localized_name: &str) -> Result<xr::Action<T>> {
+        Ok(self.handle.create_action(name, localized_name, &self.hands)?)
+    }
+
+    pub fn hand_path(&self, hand: Hand) -> xr::Path {
+        self.hands[hand.index()]
+    }
+
+    /// The state of `action` for `hand` as of the last `sync`.
+    pub fn state<T: xr::ActionInput>(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        action: &xr::Action<T>,
+        hand: Hand,
+    ) -> Result<xr::ActionState<T>> {
+        Ok(action.state(session, self.hand_path(hand))?)
+    }
+
+    /// One space per hand following `action`, indexed like `Hand::BOTH`.
+    pub fn spaces(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        action: &xr::Action<xr::Posef>,
+    ) -> Result<[xr::Space; 2]> {
+        Ok([
+            action.create_space(session.clone(), self.hands[0], xr::Posef::IDENTITY)?,
+            action.create_space(session.clone(), self.hands[1], xr::Posef::IDENTITY)?,
+        ])
+    }
+
+    /// Updates the state of every action in the set. Call once per frame before reading states.
+    ///
+    /// Any other attached set goes inactive until a sync that names it, so with more than one
+    /// set sync them together instead, e.g. through [`ControllerInput::sync`].
+    pub fn sync(&self, session: &xr::Session<xr::Vulkan>) -> Result<()> {
+        Ok(session.sync_actions(&[(&self.handle).into()])?)
+    }
+}
+
+/// The usual controller actions, declared for both hands.
+pub struct ControllerInput {
+    pub set: ActionSet,
+    pub select: xr::Action<bool>,
+    pub menu: xr::Action<bool>,
+    pub trigger: xr::Action<f32>,
+    pub squeeze: xr::Action<f32>,
+    pub thumbstick: xr::Action<xr::Vector2f>,
+    pub grip_pose: xr::Action<xr::Posef>,
+    pub aim_pose: xr::Action<xr::Posef>,
+    /// Vibration output, see [`ControllerInput::vibrate`] and `khaptics::HapticSequencer`.
+    pub haptic: xr::Action<xr::Haptic>,
+    grip_spaces: [xr::Space; 2],
+    aim_spaces: [xr::Space; 2],
+    /// The sets attached along with ours, synced along with it.
+    others: Vec<xr::ActionSet>,
+}
+
+impl ControllerInput {
+    /// Declares the actions, suggests bindings for them on every known controller and attaches
+    /// them to `session`.
+    pub fn new(instance: &xr::Instance, session: &xr::Session<xr::Vulkan>) -> Result<Self> {
+        Self::with_action_sets(instance, session, &[])
+    }
+
+    /// Like [`ControllerInput::new`], but attaches `others` along with the controller actions.
+    /// A session only takes one attach call, so action sets created elsewhere, such as
+    /// `keyegaze::EyeGaze`'s, have to come along here. [`ControllerInput::sync`] keeps them
+    /// updated too.
+    pub fn with_action_sets(
+        instance: &xr::Instance,
+        session: &xr::Session<xr::Vulkan>,
+        others: &[&xr::ActionSet],
+    ) -> Result<Self> {
+        let set = ActionSet::new(instance, "controllers", "Controllers", 0)?;
+        let select = set.action::<bool>("select", "Select")?;
+        let menu = set.action::<bool>("menu", "Menu")?;
+        let trigger = set.action::<f32>("trigger", "Trigger")?;
+        let squeeze = set.action::<f32>("squeeze", "Squeeze")?;
+        let thumbstick = set.action::<xr::Vector2f>("thumbstick", "Thumbstick")?;
+        let grip_pose = set.action::<xr::Posef>("grip_pose", "Grip Pose")?;
+        let aim_pose = set.action::<xr::Posef>("aim_pose", "Aim Pose")?;
+        let haptic = set.action::<xr::Haptic>("haptic", "Haptic")?;
+
+        Bindings::new()
+            .bind(&select, Control::Select)
+            .bind(&menu, Control::Menu)
+            .bind(&trigger, Control::Trigger)
+            .bind(&squeeze, Control::Squeeze)
+            .bind(&thumbstick, Control::Thumbstick)
+            .bind(&grip_pose, Control::GripPose)
+            .bind(&aim_pose, Control::AimPose)
+            .bind(&haptic, Control::Haptic)
+            .suggest(instance)?;
+
+        let mut sets = vec![&set.handle];
+        sets.extend_from_slice(others);
+        session.attach_action_sets(&sets)?;
+        let grip_spaces = set.spaces(session, &grip_pose)?;
+        let aim_spaces = set.spaces(session, &aim_pose)?;
+
+        Ok(Self {
+            set,
+            select,
+            menu,
+            trigger,
+            squeeze,
+            thumbstick,
+            grip_pose,
+            aim_pose,
+            haptic,
+            grip_spaces,
+            aim_spaces,
+            others: others.iter().map(|&set| set.clone()).collect(),
+        })
+    }
+
+    /// Updates the controller actions and every set passed to
+    /// [`ControllerInput::with_action_sets`]. Call once per frame before reading states.
+    pub fn sync(&self, session: &xr::Session<xr::Vulkan>) -> Result<()> {
+        let sets = std::iter::once(&self.set.handle)
+            .chain(&self.others)
+            .map(Into::into)
+            .collect::<Vec<xr::ActiveActionSet>>();
+        Ok(session.sync_actions(&sets)?)
+    }
+
+    /// Vibrates `hand`'s controller, see [`khaptics::vibrate`].
+    pub fn vibrate(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        hand: Hand,
+        amplitude: f32,
+        frequency: f32,
+        duration: Duration,
+    ) -> Result<()> {
+        khaptics::vibrate(
+            session,
+            &self.haptic,
+            self.set.hand_path(hand),
+            amplitude,
+            frequency,
+            duration,
+        )
+    }
+
+    /// Stops `hand`'s controller vibrating.
+    pub fn stop_vibration(&self, session: &xr::Session<xr::Vulkan>, hand: Hand) -> Result<()> {
+        khaptics::stop(session, &self.haptic, self.set.hand_path(hand))
+    }
+
+    /// The controller profile the runtime picked for `hand`, see [`active_profile`].
+    pub fn active_profile(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        hand: Hand,
+    ) -> Result<Option<&'static InteractionProfile>> {
+        active_profile(self.select.instance(), session, hand)
+    }
+
+    /// Reads every action for both hands as of the last `sync`, with poses located in `space` at
+    /// `time`.
+    pub fn snapshot(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        space: &xr::Space,
+        time: xr::Time,
+    ) -> Result<InputSnapshot> {
+        let hand = |hand: Hand| -> Result<HandSnapshot> {
+            Ok(HandSnapshot {
+                select: self.set.state(session, &self.select, hand)?,
+                menu: self.set.state(session, &self.menu, hand)?,
+                trigger: self.set.state(session, &self.trigger, hand)?,
+                squeeze: self.set.state(session, &self.squeeze, hand)?,
+                thumbstick: self.set.state(session, &self.thumbstick, hand)?,
+                grip: self.grip_spaces[hand.index()].locate(space, time)?,
+                aim: self.aim_spaces[hand.index()].locate(space, time)?,
+            })
+        };
+        Ok(InputSnapshot {
+            hands: [hand(Hand::Left)?, hand(Hand::Right)?],
+        })
+    }
+}
+
+/// The state of every controller action for one hand.
+#[derive(Clone, Copy)]
+pub struct HandSnapshot {
+    pub select: xr::ActionState<bool>,
+    pub menu: xr::ActionState<bool>,
+    pub trigger: xr::ActionState<f32>,
+    pub squeeze: xr::ActionState<f32>,
+    pub thumbstick: xr::ActionState<xr::Vector2f>,
+    pub grip: xr::SpaceLocation,
+    pub aim: xr::SpaceLocation,
+}
+
+impl HandSnapshot {
+    /// Whether select went down since the previous sync.
+    pub fn select_pressed(&self) -> bool {
+        self.select.current_state && self.select.changed_since_last_sync
+    }
+
+    /// Whether select came up since the previous sync.
+    pub fn select_released(&self) -> bool {
+        !self.select.current_state && self.select.changed_since_last_sync
+    }
+}
+
+/// Controller state for both hands, taken once per frame.
+#[derive(Clone, Copy)]
+pub struct InputSnapshot {
+    pub hands: [HandSnapshot; 2],
+}
+
+impl InputSnapshot {
+    pub fn hand(&self, hand: Hand) -> &HandSnapshot {
+        &self.hands[hand.index()]
+    }
+}
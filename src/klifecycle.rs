@@ -0,0 +1,330 @@
+use openxr as xr;
+
+use crate::kerror::{KaleidoError, Result};
+
+type Callback = Box<dyn FnMut()>;
+
+/// Tracks the session through every `xr::SessionState` the runtime reports and tells the
+/// application when it matters.
+///
+/// The callbacks fire as states are entered or left: `on_visible` when frames start being shown,
+/// `on_focused` when input starts arriving, `on_unfocused` when it stops (e.g. the headset was
+/// taken off or a system menu opened) and `on_stopping` right before the session is ended.
+pub struct SessionLifecycle {
+    state: xr::SessionState,
+    on_visible: Vec<Callback>,
+    on_focused: Vec<Callback>,
+    on_unfocused: Vec<Callback>,
+    on_stopping: Vec<Callback>,
+}
+
+impl Default for SessionLifecycle {
+    fn default() -> Self {
+        Self {
+            state: xr::SessionState::UNKNOWN,
+            on_visible: Vec::new(),
+            on_focused: Vec::new(),
+            on_unfocused: Vec::new(),
+            on_stopping: Vec::new(),
+        }
+    }
+}
+
+impl SessionLifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_visible(&mut self, callback: impl FnMut() + 'static) -> &mut Self {
+        self.on_visible.push(Box::new(callback));
+        self
+    }
+
+    pub fn on_focused(&mut self, callback: impl FnMut() + 'static) -> &mut Self {
+        self.on_focused.push(Box::new(callback));
+        self
+    }
+
+    pub fn on_unfocused(&mut self, callback: impl FnMut() + 'static) -> &mut Self {
+        self.on_unfocused.push(Box::new(callback));
+        self
+    }
+
+    pub fn on_stopping(&mut self, callback: impl FnMut() + 'static) -> &mut Self {
+        self.on_stopping.push(Box::new(callback));
+        self
+    }
+
+    pub fn state(&self) -> xr::SessionState {
+        self.state
+    }
+
+    /// Whether the session has been begun and frames should be waited on and submitted.
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.state,
+            xr::SessionState::READY
+                | xr::SessionState::SYNCHRONIZED
+                | xr::SessionState::VISIBLE
+                | xr::SessionState::FOCUSED
+        )
+    }
+
+    /// Whether submitted frames are shown to the user.
+    pub fn is_visible(&self) -> bool {
+        matches!(
+            self.state,
+            xr::SessionState::VISIBLE | xr::SessionState::FOCUSED
+        )
+    }
+
+    /// Whether the application receives input.
+    pub fn is_focused(&self) -> bool {
+        self.state == xr::SessionState::FOCUSED
+    }
+
+    /// Whether the session is over and the application should tear it down.
+    pub fn is_exiting(&self) -> bool {
+        matches!(
+            self.state,
+            xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING
+        )
+    }
+
+    /// Moves to `to` and runs the callbacks the transition triggers.
+    ///
+    /// Transitions the OpenXR spec doesn't allow are reported as
+    /// `KaleidoError::InvalidSessionTransition`, but the new state is taken on regardless since
+    /// the runtime's view of the session is the one that counts.
+    pub fn transition(&mut self, to: xr::SessionState) -> Result<()> {
+        let from = self.state;
+        self.state = to;
+
+        if from == xr::SessionState::FOCUSED && to != xr::SessionState::FOCUSED {
+            self.on_unfocused.iter_mut().for_each(|f| f());
+        }
+        match to {
+            xr::SessionState::VISIBLE if from != xr::SessionState::FOCUSED => {
+                self.on_visible.iter_mut().for_each(|f| f())
+            }
+            xr::SessionState::FOCUSED => self.on_focused.iter_mut().for_each(|f| f()),
+            xr::SessionState::STOPPING => self.on_stopping.iter_mut().for_each(|f| f()),
+            _ => {}
+        }
+
+        if is_valid_transition(from, to) {
+            Ok(())
+        } else {
+            Err(KaleidoError::InvalidSessionTransition { from, to })
+        }
+    }
+}
+
+/// The transitions in the session lifecycle diagram of the OpenXR spec. Loss can happen from
+/// anywhere.
+fn is_valid_transition(from: xr::SessionState, to: xr::SessionState) -> bool {
+    use xr::SessionState as S;
+    matches!(
+        (from, to),
+        (S::UNKNOWN, S::IDLE)
+            | (S::IDLE, S::READY)
+            | (S::IDLE, S::EXITING)
+            | (S::READY, S::SYNCHRONIZED)
+            | (S::SYNCHRONIZED, S::VISIBLE)
+            | (S::SYNCHRONIZED, S::STOPPING)
+            | (S::VISIBLE, S::SYNCHRONIZED)
+            | (S::VISIBLE, S::FOCUSED)
+            | (S::FOCUSED, S::VISIBLE)
+            | (S::STOPPING, S::IDLE)
+            | (_, S::LOSS_PENDING)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use xr::SessionState as S;
+
+    /// A lifecycle whose callbacks note their names in the returned log.
+    fn recorded() -> (SessionLifecycle, Rc<RefCell<Vec<&'static str>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut lifecycle = SessionLifecycle::new();
+        let note = |name| {
+            let log = log.clone();
+            move || log.borrow_mut().push(name)
+        };
+        lifecycle
+            .on_visible(note("visible"))
+            .on_focused(note("focused"))
+            .on_unfocused(note("unfocused"))
+            .on_stopping(note("stopping"));
+        (lifecycle, log)
+    }
+
+    /// Runs through `states` and returns what each transition fired.
+    fn fired(
+        lifecycle: &mut SessionLifecycle,
+        log: &RefCell<Vec<&'static str>>,
+        states: &[xr::SessionState],
+    ) -> Vec<Vec<&'static str>> {
+        states
+            .iter()
+            .map(|&state| {
+                let _ = lifecycle.transition(state);
+                log.take()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_the_spec_lifecycle() {
+        let mut lifecycle = SessionLifecycle::new();
+        for state in [
+            S::IDLE,
+            S::READY,
+            S::SYNCHRONIZED,
+            S::VISIBLE,
+            S::FOCUSED,
+            S::VISIBLE,
+            S::SYNCHRONIZED,
+            S::STOPPING,
+            S::IDLE,
+            S::EXITING,
+        ] {
+            assert!(lifecycle.transition(state).is_ok(), "rejected {:?}", state);
+            assert_eq!(lifecycle.state(), state);
+        }
+    }
+
+    #[test]
+    fn loss_is_valid_from_anywhere() {
+        for state in [
+            S::IDLE,
+            S::READY,
+            S::SYNCHRONIZED,
+            S::VISIBLE,
+            S::FOCUSED,
+            S::STOPPING,
+        ] {
+            let mut lifecycle = SessionLifecycle::new();
+            lifecycle.state = state;
+            assert!(
+                lifecycle.transition(S::LOSS_PENDING).is_ok(),
+                "from {:?}",
+                state
+            );
+            assert!(lifecycle.is_exiting());
+        }
+    }
+
+    #[test]
+    fn reports_skipped_states_but_follows_the_runtime() {
+        for (from, to) in [
+            (S::UNKNOWN, S::READY),
+            (S::IDLE, S::FOCUSED),
+            (S::READY, S::VISIBLE),
+            (S::FOCUSED, S::SYNCHRONIZED),
+            (S::FOCUSED, S::STOPPING),
+            (S::STOPPING, S::EXITING),
+            (S::EXITING, S::IDLE),
+        ] {
+            let mut lifecycle = SessionLifecycle::new();
+            lifecycle.state = from;
+            assert!(
+                matches!(
+                    lifecycle.transition(to),
+                    Err(KaleidoError::InvalidSessionTransition { from: f, to: t }) if f == from && t == to
+                ),
+                "accepted {:?} -> {:?}",
+                from,
+                to
+            );
+            assert_eq!(lifecycle.state(), to);
+        }
+    }
+
+    #[test]
+    fn fires_callbacks_as_states_are_entered_and_left() {
+        let (mut lifecycle, log) = recorded();
+        let states = [
+            S::IDLE,
+            S::READY,
+            S::SYNCHRONIZED,
+            S::VISIBLE,
+            S::FOCUSED,
+            S::VISIBLE,
+            S::FOCUSED,
+            S::VISIBLE,
+            S::SYNCHRONIZED,
+            S::STOPPING,
+            S::IDLE,
+            S::EXITING,
+        ];
+        assert_eq!(
+            fired(&mut lifecycle, &log, &states),
+            [
+                vec![],
+                vec![],
+                vec![],
+                vec!["visible"],
+                vec!["focused"],
+                // Losing focus doesn't count as becoming visible again.
+                vec!["unfocused"],
+                vec!["focused"],
+                vec!["unfocused"],
+                vec![],
+                vec!["stopping"],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn losing_a_focused_session_unfocuses_it() {
+        let (mut lifecycle, log) = recorded();
+        let states = [
+            S::IDLE,
+            S::READY,
+            S::SYNCHRONIZED,
+            S::VISIBLE,
+            S::FOCUSED,
+            S::LOSS_PENDING,
+        ];
+        assert_eq!(
+            fired(&mut lifecycle, &log, &states).last().unwrap(),
+            &["unfocused"]
+        );
+    }
+
+    #[test]
+    fn tracks_what_the_state_allows() {
+        let mut lifecycle = SessionLifecycle::new();
+        let check = |lifecycle: &SessionLifecycle| {
+            (
+                lifecycle.is_running(),
+                lifecycle.is_visible(),
+                lifecycle.is_focused(),
+                lifecycle.is_exiting(),
+            )
+        };
+        for (state, expected) in [
+            (S::IDLE, (false, false, false, false)),
+            (S::READY, (true, false, false, false)),
+            (S::SYNCHRONIZED, (true, false, false, false)),
+            (S::VISIBLE, (true, true, false, false)),
+            (S::FOCUSED, (true, true, true, false)),
+            (S::VISIBLE, (true, true, false, false)),
+            (S::SYNCHRONIZED, (true, false, false, false)),
+            (S::STOPPING, (false, false, false, false)),
+            (S::IDLE, (false, false, false, false)),
+            (S::EXITING, (false, false, false, true)),
+        ] {
+            lifecycle.transition(state).unwrap();
+            assert_eq!(check(&lifecycle), expected, "{:?}", state);
+        }
+    }
+}
@@ -0,0 +1,1089 @@
+//! Action sets, actions and bindings declared in a JSON file instead of code.
+//!
+//! A manifest looks like this:
+//!
+//! ```json
+//! {
+//!     "action_sets": [
+//!         {
+//!             "name": "gameplay",
+//!             "localized_name": "Gameplay",
+//!             "priority": 0,
+//!             "actions": [
+//!                 {
+//!                     "name": "fire",
+//!                     "localized_name": "Fire",
+//!                     "type": "boolean",
+//!                     "subaction_paths": ["/user/hand/left", "/user/hand/right"],
+//!                     "control": "select"
+//!                 },
+//!                 {
+//!                     "name": "jump",
+//!                     "localized_name": "Jump",
+//!                     "type": "boolean",
+//!                     "subaction_paths": ["/user/hand/right"]
+//!                 }
+//!             ]
+//!         }
+//!     ],
+//!     "bindings": {
+//!         "/interaction_profiles/valve/index_controller": {
+//!             "gameplay/jump": "/user/hand/right/input/a/click"
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! `type` is one of `boolean`, `float`, `vector2f`, `pose` and `haptic`. `priority`,
+//! `subaction_paths`, `control` and `bindings` are optional. An action with a `control` is bound
+//! through the [`PROFILES`] tables on every controller that has it, exactly like
+//! [`crate::kbindings::Bindings`]; entries under `bindings` name a profile and map `set/action`
+//! to one or more full binding paths, replacing the table bindings of that action on that
+//! profile.
+//!
+//! Sub-action paths and bindings are limited to `/user/hand/left` and `/user/hand/right`, the
+//! user paths the profiles in [`PROFILES`] describe.
+//!
+//! Everything is checked when the manifest is parsed: unknown members, profiles, user paths and
+//! actions, malformed names, bindings to inputs the profile's controller doesn't have, and
+//! bindings whose input can't drive the action's type (say a thumbstick bound to a boolean
+//! action) are all reported as `KaleidoError::InvalidManifest`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use openxr as xr;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::kbindings::{Control, InteractionProfile, PROFILES};
+use crate::kerror::{KaleidoError, Result};
+use crate::kinput::Hand;
+
+/// The kind of `xr::Action` a manifest action becomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionType {
+    Boolean,
+    Float,
+    Vector2f,
+    Pose,
+    Haptic,
+}
+
+impl ActionType {
+    /// The name used for the type in manifests.
+    pub fn name(self) -> &'static str {
+        match self {
+            ActionType::Boolean => "boolean",
+            ActionType::Float => "float",
+            ActionType::Vector2f => "vector2f",
+            ActionType::Pose => "pose",
+            ActionType::Haptic => "haptic",
+        }
+    }
+
+    /// Whether the component path `component`, relative to its user path, can drive an action of
+    /// this type. Runtimes convert between scalars and booleans, so both accept either.
+    fn accepts(self, component: &str) -> bool {
+        let last = component.rsplit('/').next().unwrap_or_default();
+        match self {
+            ActionType::Boolean | ActionType::Float => {
+                component.starts_with("input/")
+                    && matches!(last, "click" | "touch" | "value" | "force" | "x" | "y")
+            }
+            ActionType::Vector2f => {
+                component.starts_with("input/")
+                    && matches!(last, "thumbstick" | "trackpad" | "joystick")
+            }
+            ActionType::Pose => component.starts_with("input/") && last == "pose",
+            ActionType::Haptic => component.starts_with("output/haptic"),
+        }
+    }
+}
+
+/// One action of an [`ActionSetDecl`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionDecl {
+    pub name: String,
+    pub localized_name: String,
+    #[serde(rename = "type")]
+    pub ty: ActionType,
+    /// Top-level user paths the action can be queried for. Empty means the action isn't
+    /// filtered by user path.
+    #[serde(default)]
+    pub subaction_paths: Vec<String>,
+    /// The logical control bound through the [`PROFILES`] tables, if any.
+    #[serde(default)]
+    pub control: Option<Control>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionSetDecl {
+    pub name: String,
+    pub localized_name: String,
+    #[serde(default)]
+    pub priority: u32,
+    pub actions: Vec<ActionDecl>,
+}
+
+/// The bindings a manifest spells out for one interaction profile.
+#[derive(Debug, Clone)]
+pub struct ProfileBindings {
+    pub profile: String,
+    /// `(set/action, binding path)` pairs.
+    pub bindings: Vec<(String, String)>,
+}
+
+/// A validated action manifest, ready to be turned into actions with [`ActionManifest::create`].
+#[derive(Debug, Clone)]
+pub struct ActionManifest {
+    pub action_sets: Vec<ActionSetDecl>,
+    pub bindings: Vec<ProfileBindings>,
+}
+
+/// A manifest as it's written, before validation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    action_sets: Vec<ActionSetDecl>,
+    #[serde(default)]
+    bindings: Members<Members<BindingPaths>>,
+}
+
+/// An object's members in file order. Unlike a map, duplicate names are an error rather than
+/// the last one silently winning.
+struct Members<T>(Vec<(String, T)>);
+
+impl<T> Default for Members<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Members<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct MembersVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for MembersVisitor<T> {
+            type Value = Members<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut members: Vec<(String, T)> = Vec::new();
+                while let Some(name) = map.next_key::<String>()? {
+                    if members.iter().any(|(n, _)| *n == name) {
+                        return Err(de::Error::custom(format!("duplicate member \"{}\"", name)));
+                    }
+                    let value = map.next_value()?;
+                    members.push((name, value));
+                }
+                Ok(Members(members))
+            }
+        }
+
+        deserializer.deserialize_map(MembersVisitor(PhantomData))
+    }
+}
+
+/// The binding paths of one action: a single string or an array of them.
+struct BindingPaths(Vec<String>);
+
+impl<'de> Deserialize<'de> for BindingPaths {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct PathsVisitor;
+
+        impl<'de> Visitor<'de> for PathsVisitor {
+            type Value = BindingPaths;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a binding path or an array of them")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> std::result::Result<Self::Value, E> {
+                Ok(BindingPaths(vec![path.to_owned()]))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut paths = Vec::new();
+                while let Some(path) = seq.next_element()? {
+                    paths.push(path);
+                }
+                Ok(BindingPaths(paths))
+            }
+        }
+
+        deserializer.deserialize_any(PathsVisitor)
+    }
+}
+
+impl ActionManifest {
+    /// Reads and validates the manifest at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| KaleidoError::InvalidManifest(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&text)
+    }
+
+    /// Parses and validates a manifest.
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: ManifestFile =
+            serde_json::from_str(text).map_err(|e| KaleidoError::InvalidManifest(e.to_string()))?;
+
+        for (i, set) in file.action_sets.iter().enumerate() {
+            let at = format!("action_sets[{}]", i);
+            check_action_set(set, &at)?;
+            if file.action_sets[..i].iter().any(|s| s.name == set.name) {
+                return Err(invalid(
+                    &at,
+                    format!("duplicate action set \"{}\"", set.name),
+                ));
+            }
+        }
+
+        let mut manifest = Self {
+            action_sets: file.action_sets,
+            bindings: Vec::new(),
+        };
+        for (profile, entries) in file.bindings.0 {
+            let profile_bindings = manifest.check_profile_bindings(profile, entries)?;
+            manifest.bindings.push(profile_bindings);
+        }
+        Ok(manifest)
+    }
+
+    /// Looks up an action by its `set/action` name.
+    pub fn action(&self, name: &str) -> Option<&ActionDecl> {
+        let (set, action) = name.split_once('/')?;
+        self.action_sets
+            .iter()
+            .find(|s| s.name == set)?
+            .actions
+            .iter()
+            .find(|a| a.name == action)
+    }
+
+    fn check_profile_bindings(
+        &self,
+        profile: String,
+        entries: Members<BindingPaths>,
+    ) -> Result<ProfileBindings> {
+        let at = format!("bindings[\"{}\"]", profile);
+        let Some(known) = InteractionProfile::find(&profile) else {
+            return Err(invalid(&at, "unknown interaction profile"));
+        };
+
+        let mut bindings = Vec::new();
+        for (name, BindingPaths(paths)) in entries.0 {
+            let at = format!("{}[\"{}\"]", at, name);
+            let action = self
+                .action(&name)
+                .ok_or_else(|| invalid(&at, "no such action, expected \"set/action\""))?;
+            for path in paths {
+                check_binding(known, action, &path).map_err(|message| invalid(&at, message))?;
+                bindings.push((name.clone(), path));
+            }
+        }
+        Ok(ProfileBindings { profile, bindings })
+    }
+
+    /// Creates the action sets and actions, suggests their bindings for every profile the
+    /// instance supports and attaches them to `session`.
+    ///
+    /// A session only takes one attach call, so this can't be combined with
+    /// `kinput::ControllerInput`, which attaches its own set; declare those actions in the
+    /// manifest instead. Other sets go through [`ActionManifest::create_with_action_sets`].
+    pub fn create(
+        &self,
+        instance: &xr::Instance,
+        session: &xr::Session<xr::Vulkan>,
+    ) -> Result<Actions> {
+        self.create_with_action_sets(instance, session, &[])
+    }
+
+    /// Like [`ActionManifest::create`], but attaches `others` along with the manifest's sets,
+    /// e.g. `keyegaze::EyeGaze`'s. [`Actions::sync`] keeps them updated too.
+    pub fn create_with_action_sets(
+        &self,
+        instance: &xr::Instance,
+        session: &xr::Session<xr::Vulkan>,
+        others: &[&xr::ActionSet],
+    ) -> Result<Actions> {
+        let mut sets = Vec::new();
+        let mut actions = HashMap::new();
+        for set_decl in &self.action_sets {
+            let set = instance.create_action_set(
+                &set_decl.name,
+                &set_decl.localized_name,
+                set_decl.priority,
+            )?;
+            for decl in &set_decl.actions {
+                let paths = decl
+                    .subaction_paths
+                    .iter()
+                    .map(|path| instance.string_to_path(path))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let (name, localized, paths) = (&decl.name, &decl.localized_name, &paths[..]);
+                let action = match decl.ty {
+                    ActionType::Boolean => {
+                        AnyAction::Boolean(set.create_action(name, localized, paths)?)
+                    }
+                    ActionType::Float => {
+                        AnyAction::Float(set.create_action(name, localized, paths)?)
+                    }
+                    ActionType::Vector2f => {
+                        AnyAction::Vector2f(set.create_action(name, localized, paths)?)
+                    }
+                    ActionType::Pose => AnyAction::Pose(set.create_action(name, localized, paths)?),
+                    ActionType::Haptic => {
+                        AnyAction::Haptic(set.create_action(name, localized, paths)?)
+                    }
+                };
+                actions.insert(format!("{}/{}", set_decl.name, decl.name), action);
+            }
+            sets.push((set_decl.name.clone(), set));
+        }
+
+        for profile in PROFILES.iter() {
+            if !(profile.available)(instance.exts()) {
+                if self.bindings.iter().any(|b| b.profile == profile.path) {
+                    log::info!(
+                        "skipping manifest bindings for {}, the instance doesn't support it",
+                        profile.name
+                    );
+                }
+                continue;
+            }
+            let paths = self.binding_paths(profile);
+            if paths.is_empty() {
+                continue;
+            }
+            let mut bindings = Vec::with_capacity(paths.len());
+            for (name, path) in &paths {
+                // The manifest's members are public, so they may have changed since validation.
+                let action = actions.get(name).ok_or_else(|| {
+                    invalid(
+                        &format!("bindings[\"{}\"][\"{}\"]", profile.path, name),
+                        "no such action, expected \"set/action\"",
+                    )
+                })?;
+                let path = instance.string_to_path(path)?;
+                bindings.push(match action {
+                    AnyAction::Boolean(a) => xr::Binding::new(a, path),
+                    AnyAction::Float(a) => xr::Binding::new(a, path),
+                    AnyAction::Vector2f(a) => xr::Binding::new(a, path),
+                    AnyAction::Pose(a) => xr::Binding::new(a, path),
+                    AnyAction::Haptic(a) => xr::Binding::new(a, path),
+                });
+            }
+            instance.suggest_interaction_profile_bindings(
+                instance.string_to_path(profile.path)?,
+                &bindings,
+            )?;
+        }
+
+        let mut attached = sets.iter().map(|(_, set)| set).collect::<Vec<_>>();
+        attached.extend_from_slice(others);
+        session.attach_action_sets(&attached)?;
+        Ok(Actions {
+            sets,
+            actions,
+            others: others.iter().map(|&set| set.clone()).collect(),
+        })
+    }
+
+    /// Every `(set/action, binding path)` pair to suggest for `profile`: the explicit bindings,
+    /// then the table bindings of actions with a control and no explicit binding.
+    fn binding_paths(&self, profile: &InteractionProfile) -> Vec<(String, String)> {
+        let explicit = self
+            .bindings
+            .iter()
+            .filter(|b| b.profile == profile.path)
+            .flat_map(|b| b.bindings.iter().cloned())
+            .collect::<Vec<_>>();
+
+        let mut paths = explicit.clone();
+        for set in &self.action_sets {
+            for action in &set.actions {
+                let Some(control) = action.control else {
+                    continue;
+                };
+                let name = format!("{}/{}", set.name, action.name);
+                if explicit.iter().any(|(a, _)| *a == name) {
+                    continue;
+                }
+                for hand in control_hands(action) {
+                    if let Some(component) = profile.component(control, hand) {
+                        paths.push((name.clone(), format!("{}/{}", hand.path(), component)));
+                    }
+                }
+            }
+        }
+        paths
+    }
+}
+
+/// An action created from a manifest.
+pub enum AnyAction {
+    Boolean(xr::Action<bool>),
+    Float(xr::Action<f32>),
+    Vector2f(xr::Action<xr::Vector2f>),
+    Pose(xr::Action<xr::Posef>),
+    Haptic(xr::Action<xr::Haptic>),
+}
+
+/// The action sets and actions of an [`ActionManifest`], looked up by name.
+pub struct Actions {
+    sets: Vec<(String, xr::ActionSet)>,
+    actions: HashMap<String, AnyAction>,
+    /// The sets attached along with the manifest's, synced along with them.
+    others: Vec<xr::ActionSet>,
+}
+
+impl Actions {
+    pub fn set(&self, name: &str) -> Option<&xr::ActionSet> {
+        self.sets
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, set)| set)
+    }
+
+    /// The action called `set/action`, whatever its type.
+    pub fn get(&self, name: &str) -> Option<&AnyAction> {
+        self.actions.get(name)
+    }
+
+    pub fn boolean(&self, name: &str) -> Option<&xr::Action<bool>> {
+        match self.get(name)? {
+            AnyAction::Boolean(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    pub fn float(&self, name: &str) -> Option<&xr::Action<f32>> {
+        match self.get(name)? {
+            AnyAction::Float(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    pub fn vector2f(&self, name: &str) -> Option<&xr::Action<xr::Vector2f>> {
+        match self.get(name)? {
+            AnyAction::Vector2f(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    pub fn pose(&self, name: &str) -> Option<&xr::Action<xr::Posef>> {
+        match self.get(name)? {
+            AnyAction::Pose(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    pub fn haptic(&self, name: &str) -> Option<&xr::Action<xr::Haptic>> {
+        match self.get(name)? {
+            AnyAction::Haptic(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    /// Updates the state of every action in every set, including those passed to
+    /// [`ActionManifest::create_with_action_sets`]. Call once per frame before reading states.
+    pub fn sync(&self, session: &xr::Session<xr::Vulkan>) -> Result<()> {
+        let sets = self
+            .sets
+            .iter()
+            .map(|(_, set)| set)
+            .chain(&self.others)
+            .map(Into::into)
+            .collect::<Vec<xr::ActiveActionSet>>();
+        Ok(session.sync_actions(&sets)?)
+    }
+}
+
+fn invalid(at: &str, message: impl std::fmt::Display) -> KaleidoError {
+    KaleidoError::InvalidManifest(format!("{}: {}", at, message))
+}
+
+/// OpenXR names are lowercase ASCII letters, digits, `-`, `_` and `.`, shorter than 64 bytes.
+fn check_name(name: &str, at: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() < 64
+        && name
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.'));
+    if !valid {
+        return Err(invalid(
+            at,
+            format!(
+                "\"{}\" is not a valid name, use lowercase letters, digits, '-', '_' and '.'",
+                name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check_localized_name(localized: &str, at: &str) -> Result<()> {
+    if localized.is_empty() || localized.len() >= 128 {
+        return Err(invalid(
+            at,
+            "expected a non-empty name shorter than 128 bytes",
+        ));
+    }
+    Ok(())
+}
+
+fn check_action_set(set: &ActionSetDecl, at: &str) -> Result<()> {
+    check_name(&set.name, &format!("{}.name", at))?;
+    check_localized_name(&set.localized_name, &format!("{}.localized_name", at))?;
+    for (i, action) in set.actions.iter().enumerate() {
+        let action_at = format!("{}.actions[{}]", at, i);
+        check_action(action, &action_at)?;
+        if set.actions[..i].iter().any(|a| a.name == action.name) {
+            return Err(invalid(
+                &action_at,
+                format!("duplicate action \"{}\"", action.name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_action(action: &ActionDecl, at: &str) -> Result<()> {
+    check_name(&action.name, &format!("{}.name", at))?;
+    check_localized_name(&action.localized_name, &format!("{}.localized_name", at))?;
+
+    let paths_at = format!("{}.subaction_paths", at);
+    for (i, path) in action.subaction_paths.iter().enumerate() {
+        if !Hand::BOTH.iter().any(|hand| hand.path() == path) {
+            return Err(invalid(
+                &paths_at,
+                format!("unknown user path \"{}\"", path),
+            ));
+        }
+        if action.subaction_paths[..i].contains(path) {
+            return Err(invalid(
+                &paths_at,
+                format!("duplicate user path \"{}\"", path),
+            ));
+        }
+    }
+
+    if action.control.is_some() {
+        check_control(action).map_err(|message| invalid(&format!("{}.control", at), message))?;
+    }
+    Ok(())
+}
+
+/// The hands an action with a control gets table bindings for.
+fn control_hands(action: &ActionDecl) -> impl Iterator<Item = Hand> + '_ {
+    Hand::BOTH.into_iter().filter(|hand| {
+        action.subaction_paths.is_empty() || action.subaction_paths.iter().any(|p| p == hand.path())
+    })
+}
+
+/// Checks that `action`'s control exists for its hands and fits its type on every profile.
+fn check_control(action: &ActionDecl) -> std::result::Result<(), String> {
+    let control = action.control.expect("checked action has a control");
+    for profile in PROFILES.iter() {
+        for hand in control_hands(action) {
+            if let Some(component) = profile.component(control, hand) {
+                if !action.ty.accepts(component) {
+                    return Err(format!(
+                        "{:?} is {}/{} on {}, which can't drive a {} action",
+                        control,
+                        hand.path(),
+                        component,
+                        profile.name,
+                        action.ty.name()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the full binding path `path` names a hand the action accepts and an input or
+/// output `profile` has that fits the action's type.
+fn check_binding(
+    profile: &InteractionProfile,
+    action: &ActionDecl,
+    path: &str,
+) -> std::result::Result<(), String> {
+    let (hand, component) = Hand::BOTH
+        .into_iter()
+        .find_map(|hand| Some((hand, path.strip_prefix(hand.path())?.strip_prefix('/')?)))
+        .ok_or_else(|| {
+            format!(
+                "\"{}\" doesn't start with /user/hand/left or /user/hand/right",
+                path
+            )
+        })?;
+    if !action.subaction_paths.is_empty()
+        && !action.subaction_paths.iter().any(|p| p == hand.path())
+    {
+        return Err(format!(
+            "\"{}\" is not one of the action's sub-action paths",
+            hand.path()
+        ));
+    }
+    if !component.starts_with("input/") && !component.starts_with("output/") {
+        return Err(format!("\"{}\" is not an input or output path", path));
+    }
+    if !profile.has_path(hand, component) {
+        return Err(format!(
+            "\"{}\" doesn't exist on the {}",
+            path, profile.name
+        ));
+    }
+    if !action.ty.accepts(component) {
+        return Err(format!(
+            "\"{}\" can't drive a {} action",
+            path,
+            action.ty.name()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manifest with one `game` set holding `actions`, and `bindings` as its bindings member.
+    fn manifest(actions: &str, bindings: &str) -> String {
+        format!(
+            r#"{{
+                "action_sets": [
+                    {{ "name": "game", "localized_name": "Game", "actions": [{}] }}
+                ],
+                "bindings": {{ {} }}
+            }}"#,
+            actions, bindings
+        )
+    }
+
+    const FIRE: &str = r#"{
+        "name": "fire",
+        "localized_name": "Fire",
+        "type": "boolean",
+        "subaction_paths": ["/user/hand/left", "/user/hand/right"]
+    }"#;
+
+    fn error(text: &str) -> String {
+        match ActionManifest::parse(text) {
+            Err(KaleidoError::InvalidManifest(message)) => message,
+            Err(e) => panic!("expected an invalid manifest error, got {}", e),
+            Ok(_) => panic!("accepted {}", text),
+        }
+    }
+
+    fn assert_error(text: &str, expected: &str) {
+        let message = error(text);
+        assert!(
+            message.contains(expected),
+            "expected an error containing {:?}, got {:?}",
+            expected,
+            message
+        );
+    }
+
+    #[test]
+    fn parses_a_valid_manifest() {
+        let manifest = ActionManifest::parse(
+            r#"{
+                "action_sets": [
+                    {
+                        "name": "gameplay",
+                        "localized_name": "Gameplay",
+                        "priority": 2,
+                        "actions": [
+                            {
+                                "name": "fire",
+                                "localized_name": "Fire",
+                                "type": "boolean",
+                                "subaction_paths": ["/user/hand/left", "/user/hand/right"],
+                                "control": "select"
+                            },
+                            {
+                                "name": "jump",
+                                "localized_name": "Jump",
+                                "type": "boolean",
+                                "subaction_paths": ["/user/hand/right"]
+                            },
+                            { "name": "move", "localized_name": "Move", "type": "vector2f" }
+                        ]
+                    }
+                ],
+                "bindings": {
+                    "/interaction_profiles/valve/index_controller": {
+                        "gameplay/jump": "/user/hand/right/input/a/click",
+                        "gameplay/move": [
+                            "/user/hand/left/input/thumbstick",
+                            "/user/hand/right/input/trackpad"
+                        ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let [set] = &manifest.action_sets[..] else {
+            panic!("expected one action set");
+        };
+        assert_eq!((set.name.as_str(), set.priority), ("gameplay", 2));
+        assert_eq!(set.actions.len(), 3);
+
+        let fire = manifest.action("gameplay/fire").unwrap();
+        assert_eq!(fire.ty, ActionType::Boolean);
+        assert_eq!(fire.control, Some(Control::Select));
+        assert_eq!(fire.subaction_paths.len(), 2);
+        let jump = manifest.action("gameplay/jump").unwrap();
+        assert_eq!(jump.subaction_paths, ["/user/hand/right"]);
+        assert_eq!(jump.control, None);
+        let movement = manifest.action("gameplay/move").unwrap();
+        assert_eq!(movement.ty, ActionType::Vector2f);
+        assert!(movement.subaction_paths.is_empty());
+        assert!(manifest.action("gameplay/missing").is_none());
+        assert!(manifest.action("fire").is_none());
+
+        let [index] = &manifest.bindings[..] else {
+            panic!("expected bindings for one profile");
+        };
+        assert_eq!(
+            index.profile,
+            "/interaction_profiles/valve/index_controller"
+        );
+        assert_eq!(
+            index.bindings,
+            [
+                (
+                    "gameplay/jump".into(),
+                    "/user/hand/right/input/a/click".into()
+                ),
+                (
+                    "gameplay/move".into(),
+                    "/user/hand/left/input/thumbstick".into()
+                ),
+                (
+                    "gameplay/move".into(),
+                    "/user/hand/right/input/trackpad".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn bindings_are_optional() {
+        let manifest = ActionManifest::parse(
+            r#"{ "action_sets": [{ "name": "a", "localized_name": "A", "actions": [] }] }"#,
+        )
+        .unwrap();
+        assert!(manifest.bindings.is_empty());
+        assert_eq!(manifest.action_sets[0].priority, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        assert_error("{ \"action_sets\": [", "line 1");
+        assert_error(r#"{ "action_sets": [], }"#, "trailing comma");
+        assert_error(r#"{ "action_sets": [] } []"#, "trailing characters");
+    }
+
+    #[test]
+    fn rejects_deep_nesting_without_overflowing_the_stack() {
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        for text in [
+            nested.clone(),
+            format!(r#"{{ "action_sets": {} }}"#, nested),
+            manifest(
+                FIRE,
+                &format!(
+                    r#""/interaction_profiles/khr/simple_controller": {{ "game/fire": {} }}"#,
+                    nested
+                ),
+            ),
+        ] {
+            error(&text);
+        }
+    }
+
+    #[test]
+    fn keeps_bindings_in_file_order() {
+        let manifest = ActionManifest::parse(&manifest(
+            FIRE,
+            r#""/interaction_profiles/valve/index_controller": { "game/fire": "/user/hand/left/input/a/click" },
+            "/interaction_profiles/htc/vive_controller": { "game/fire": "/user/hand/left/input/trigger/click" }"#,
+        ))
+        .unwrap();
+        let profiles: Vec<_> = manifest
+            .bindings
+            .iter()
+            .map(|b| b.profile.as_str())
+            .collect();
+        assert_eq!(
+            profiles,
+            [
+                "/interaction_profiles/valve/index_controller",
+                "/interaction_profiles/htc/vive_controller"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_missing_members() {
+        assert_error(
+            r#"{ "action_sets": [], "extra": 1 }"#,
+            "unknown field `extra`, expected `action_sets` or `bindings`",
+        );
+        assert_error(r#"{}"#, "missing field `action_sets`");
+        assert_error(
+            &manifest(&FIRE.replace("\"type\"", "\"kind\""), ""),
+            "unknown field `kind`",
+        );
+        assert_error(
+            r#"{ "action_sets": [{ "name": "a", "localized_name": "A" }] }"#,
+            "missing field `actions`",
+        );
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_kind() {
+        assert_error(
+            r#"{ "action_sets": {} }"#,
+            "invalid type: map, expected a sequence",
+        );
+        assert_error(
+            "3",
+            "invalid type: integer `3`, expected struct ManifestFile",
+        );
+        assert_error(
+            &manifest(&FIRE.replace("\"Fire\"", "7"), ""),
+            "invalid type: integer `7`, expected a string",
+        );
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        assert_error(
+            &manifest(&FIRE.replace("\"fire\"", "\"Fire Button\""), ""),
+            "\"Fire Button\" is not a valid name",
+        );
+        assert_error(
+            &manifest(&FIRE.replace("\"Fire\"", "\"\""), ""),
+            "expected a non-empty name",
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_priority() {
+        assert_error(
+            r#"{ "action_sets": [{ "name": "a", "localized_name": "A", "priority": -1, "actions": [] }] }"#,
+            "invalid value: integer `-1`, expected u32 at line 1",
+        );
+        assert_error(
+            r#"{ "action_sets": [{ "name": "a", "localized_name": "A", "priority": 1.5, "actions": [] }] }"#,
+            "expected u32",
+        );
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        assert_error(
+            &manifest(&format!("{}, {}", FIRE, FIRE), ""),
+            "action_sets[0].actions[1]: duplicate action \"fire\"",
+        );
+        assert_error(
+            r#"{ "action_sets": [
+                { "name": "a", "localized_name": "A", "actions": [] },
+                { "name": "a", "localized_name": "A", "actions": [] }
+            ] }"#,
+            "action_sets[1]: duplicate action set \"a\"",
+        );
+        assert_error(
+            &manifest(
+                &FIRE.replace("\"/user/hand/right\"", "\"/user/hand/left\""),
+                "",
+            ),
+            "duplicate user path \"/user/hand/left\"",
+        );
+        assert_error(
+            &manifest(
+                &FIRE.replace("\"Fire\",", "\"Fire\", \"name\": \"shoot\","),
+                "",
+            ),
+            "duplicate field `name`",
+        );
+        assert_error(
+            &manifest(
+                FIRE,
+                r#""/interaction_profiles/khr/simple_controller": {
+                    "game/fire": "/user/hand/left/input/select/click",
+                    "game/fire": "/user/hand/right/input/select/click"
+                }"#,
+            ),
+            "duplicate member \"game/fire\"",
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_type() {
+        assert_error(
+            &manifest(&FIRE.replace("\"boolean\"", "\"bool\""), ""),
+            "unknown variant `bool`, expected one of `boolean`, `float`, `vector2f`, `pose`, `haptic`",
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_sub_action_paths() {
+        assert_error(
+            &manifest(&FIRE.replace("/user/hand/right", "/user/foot/right"), ""),
+            "unknown user path \"/user/foot/right\"",
+        );
+        // No profile describes anything but the hands, so nothing else could be bound.
+        for path in ["/user/head", "/user/gamepad", "/user/treadmill"] {
+            assert_error(
+                &manifest(&FIRE.replace("/user/hand/right", path), ""),
+                &format!("unknown user path \"{}\"", path),
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_controls() {
+        assert_error(
+            &manifest(
+                &FIRE.replace("\"Fire\",", "\"Fire\", \"control\": \"fire\","),
+                "",
+            ),
+            "unknown variant `fire`, expected one of `select`, `menu`,",
+        );
+        // The thumbstick can't drive a boolean action.
+        assert_error(
+            &manifest(
+                &FIRE.replace("\"Fire\",", "\"Fire\", \"control\": \"thumbstick\","),
+                "",
+            ),
+            "can't drive a boolean action",
+        );
+    }
+
+    #[test]
+    fn rejects_an_unknown_profile() {
+        assert_error(
+            &manifest(
+                FIRE,
+                r#""/interaction_profiles/acme/wand": { "game/fire": "/user/hand/left/input/select/click" }"#,
+            ),
+            "bindings[\"/interaction_profiles/acme/wand\"]: unknown interaction profile",
+        );
+    }
+
+    #[test]
+    fn rejects_bindings_for_unknown_actions() {
+        assert_error(
+            &manifest(
+                FIRE,
+                r#""/interaction_profiles/khr/simple_controller": { "game/jump": "/user/hand/left/input/select/click" }"#,
+            ),
+            "[\"game/jump\"]: no such action",
+        );
+    }
+
+    #[test]
+    fn rejects_bad_paths() {
+        let bind = |path: &str| {
+            manifest(
+                FIRE,
+                &format!(
+                    r#""/interaction_profiles/htc/vive_controller": {{ "game/fire": "{}" }}"#,
+                    path
+                ),
+            )
+        };
+        assert_error(
+            &bind("/user/foot/left/input/trigger/click"),
+            "doesn't start with /user/hand/left or /user/hand/right",
+        );
+        assert_error(
+            &bind("/user/hand/left/trigger/click"),
+            "is not an input or output path",
+        );
+        // The Vive wand has no face buttons.
+        assert_error(
+            &bind("/user/hand/right/input/a/click"),
+            "\"/user/hand/right/input/a/click\" doesn't exist on the HTC Vive",
+        );
+        assert_error(
+            &bind("/user/head/input/volume_up/click"),
+            "doesn't start with /user/hand/left or /user/hand/right",
+        );
+        assert_error(
+            &manifest(
+                &FIRE.replace(", \"/user/hand/right\"", ""),
+                r#""/interaction_profiles/htc/vive_controller": { "game/fire": "/user/hand/right/input/trigger/click" }"#,
+            ),
+            "\"/user/hand/right\" is not one of the action's sub-action paths",
+        );
+        assert_error(
+            &manifest(
+                FIRE,
+                r#""/interaction_profiles/oculus/touch_controller": { "game/fire": "/user/hand/right/input/x/click" }"#,
+            ),
+            "doesn't exist on the Oculus Touch",
+        );
+        assert_error(
+            &manifest(
+                FIRE,
+                r#""/interaction_profiles/khr/simple_controller": { "game/fire": 3 }"#,
+            ),
+            "invalid type: integer `3`, expected a binding path or an array of them",
+        );
+    }
+
+    #[test]
+    fn rejects_type_mismatches() {
+        let bind = |action: &str, path: &str| {
+            manifest(
+                action,
+                &format!(
+                    r#""/interaction_profiles/valve/index_controller": {{ "game/fire": "{}" }}"#,
+                    path
+                ),
+            )
+        };
+        assert_error(
+            &bind(FIRE, "/user/hand/left/input/thumbstick"),
+            "\"/user/hand/left/input/thumbstick\" can't drive a boolean action",
+        );
+        assert_error(
+            &bind(FIRE, "/user/hand/left/output/haptic"),
+            "can't drive a boolean action",
+        );
+        let aim = FIRE.replace("\"boolean\"", "\"pose\"");
+        assert_error(
+            &bind(&aim, "/user/hand/left/input/trigger/value"),
+            "can't drive a pose action",
+        );
+        // Scalars and booleans convert into each other.
+        ActionManifest::parse(&bind(FIRE, "/user/hand/left/input/trigger/value")).unwrap();
+        ActionManifest::parse(&bind(&aim, "/user/hand/left/input/aim/pose")).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! The little vector math kaleido needs on OpenXR's own types.
+
+use openxr as xr;
+
+pub(crate) fn vec3(x: f32, y: f32, z: f32) -> xr::Vector3f {
+    xr::Vector3f { x, y, z }
+}
+
+pub(crate) fn add(a: xr::Vector3f, b: xr::Vector3f) -> xr::Vector3f {
+    vec3(a.x + b.x, a.y + b.y, a.z + b.z)
+}
+
+pub(crate) fn sub(a: xr::Vector3f, b: xr::Vector3f) -> xr::Vector3f {
+    vec3(a.x - b.x, a.y - b.y, a.z - b.z)
+}
+
+pub(crate) fn scale(v: xr::Vector3f, s: f32) -> xr::Vector3f {
+    vec3(v.x * s, v.y * s, v.z * s)
+}
+
+pub(crate) fn dot(a: xr::Vector3f, b: xr::Vector3f) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub(crate) fn cross(a: xr::Vector3f, b: xr::Vector3f) -> xr::Vector3f {
+    vec3(
+        a.y * b.z - a.z * b.y,
+        a.z * b.x - a.x * b.z,
+        a.x * b.y - a.y * b.x,
+    )
+}
+
+pub(crate) fn length(v: xr::Vector3f) -> f32 {
+    dot(v, v).sqrt()
+}
+
+pub(crate) fn distance(a: xr::Vector3f, b: xr::Vector3f) -> f32 {
+    length(sub(a, b))
+}
+
+/// `v` scaled to unit length; the zero vector stays as it is.
+pub(crate) fn normalize(v: xr::Vector3f) -> xr::Vector3f {
+    let len = length(v);
+    if len > f32::EPSILON {
+        scale(v, 1.0 / len)
+    } else {
+        v
+    }
+}
+
+/// `v` rotated by the unit quaternion `q`.
+pub(crate) fn rotate(q: xr::Quaternionf, v: xr::Vector3f) -> xr::Vector3f {
+    let u = vec3(q.x, q.y, q.z);
+    let t = scale(cross(u, v), 2.0);
+    add(add(v, scale(t, q.w)), cross(u, t))
+}
+
+/// `pose`'s forward direction. OpenXR poses look down their -Z axis.
+pub(crate) fn forward(pose: xr::Posef) -> xr::Vector3f {
+    rotate(pose.orientation, vec3(0.0, 0.0, -1.0))
+}
+
+/// The rotation `b` followed by `a`.
+pub(crate) fn mul_quat(a: xr::Quaternionf, b: xr::Quaternionf) -> xr::Quaternionf {
+    xr::Quaternionf {
+        x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+        y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+        z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+    }
+}
+
+/// The inverse of the unit quaternion `q`.
+pub(crate) fn conjugate(q: xr::Quaternionf) -> xr::Quaternionf {
+    xr::Quaternionf {
+        x: -q.x,
+        y: -q.y,
+        z: -q.z,
+        w: q.w,
+    }
+}
+
+/// `b` expressed in the space `a` is expressed in, where `b` is relative to `a`.
+pub(crate) fn compose(a: xr::Posef, b: xr::Posef) -> xr::Posef {
+    xr::Posef {
+        orientation: mul_quat(a.orientation, b.orientation),
+        position: add(a.position, rotate(a.orientation, b.position)),
+    }
+}
+
+/// The pose that undoes `pose`, so `compose(inverse(p), p)` is the identity.
+pub(crate) fn inverse(pose: xr::Posef) -> xr::Posef {
+    let orientation = conjugate(pose.orientation);
+    xr::Posef {
+        orientation,
+        position: scale(rotate(orientation, pose.position), -1.0),
+    }
+}
+
+/// A column-major 4x4 matrix, laid out the way GLSL reads a `mat4`.
+pub(crate) type Mat4 = [f32; 16];
+
+/// The matrix that takes points relative to `pose` into the space `pose` is expressed in.
+pub(crate) fn pose_matrix(pose: xr::Posef) -> Mat4 {
+    let x = rotate(pose.orientation, vec3(1.0, 0.0, 0.0));
+    let y = rotate(pose.orientation, vec3(0.0, 1.0, 0.0));
+    let z = rotate(pose.orientation, vec3(0.0, 0.0, 1.0));
+    let p = pose.position;
+    [
+        x.x, x.y, x.z, 0.0, //
+        y.x, y.y, y.z, 0.0, //
+        z.x, z.y, z.z, 0.0, //
+        p.x, p.y, p.z, 1.0,
+    ]
+}
+
+/// The projection for a view with field of view `fov`, into Vulkan's clip space: +Y down and
+/// depth from 0 at `near` to 1 at `far`.
+pub(crate) fn projection(fov: xr::Fovf, near: f32, far: f32) -> Mat4 {
+    let (left, right) = (fov.angle_left.tan(), fov.angle_right.tan());
+    let (up, down) = (fov.angle_up.tan(), fov.angle_down.tan());
+    let (width, height) = (right - left, down - up);
+    let mut m = [0.0; 16];
+    m[0] = 2.0 / width;
+    m[5] = 2.0 / height;
+    m[8] = (right + left) / width;
+    m[9] = (up + down) / height;
+    m[10] = -far / (far - near);
+    m[11] = -1.0;
+    m[14] = -far * near / (far - near);
+    m
+}
+
+/// The matrix that applies `b`, then `a`.
+pub(crate) fn mul_mat4(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut m = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            m[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn yaw(angle: f32) -> xr::Quaternionf {
+        xr::Quaternionf {
+            x: 0.0,
+            y: (angle / 2.0).sin(),
+            z: 0.0,
+            w: (angle / 2.0).cos(),
+        }
+    }
+
+    fn pose(orientation: xr::Quaternionf, position: xr::Vector3f) -> xr::Posef {
+        xr::Posef {
+            orientation,
+            position,
+        }
+    }
+
+    /// A pose with every component of its rotation and position in play.
+    fn skewed() -> xr::Posef {
+        let q = xr::Quaternionf {
+            x: 0.3,
+            y: -0.5,
+            z: 0.1,
+            w: 0.8,
+        };
+        let len = (q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w).sqrt();
+        pose(
+            xr::Quaternionf {
+                x: q.x / len,
+                y: q.y / len,
+                z: q.z / len,
+                w: q.w / len,
+            },
+            vec3(1.5, -0.25, 3.0),
+        )
+    }
+
+    fn assert_vec(actual: xr::Vector3f, expected: xr::Vector3f) {
+        assert!(
+            distance(actual, expected) < 1e-5,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    fn assert_identity(pose: xr::Posef) {
+        assert_vec(pose.position, vec3(0.0, 0.0, 0.0));
+        for v in [
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ] {
+            assert_vec(rotate(pose.orientation, v), v);
+        }
+    }
+
+    /// `m` applied to the point `p`, after the perspective divide.
+    fn transform(m: &Mat4, p: xr::Vector3f) -> xr::Vector3f {
+        let row = |r: usize| m[r] * p.x + m[4 + r] * p.y + m[8 + r] * p.z + m[12 + r];
+        let w = row(3);
+        vec3(row(0) / w, row(1) / w, row(2) / w)
+    }
+
+    #[test]
+    fn rotates_by_quaternions() {
+        // A quarter turn to the left about +Y takes +X to -Z and -Z to -X.
+        assert_vec(
+            rotate(yaw(FRAC_PI_2), vec3(1.0, 0.0, 0.0)),
+            vec3(0.0, 0.0, -1.0),
+        );
+        assert_vec(
+            rotate(yaw(FRAC_PI_2), vec3(0.0, 0.0, -1.0)),
+            vec3(-1.0, 0.0, 0.0),
+        );
+        assert_vec(
+            rotate(yaw(FRAC_PI_2), vec3(0.0, 2.0, 0.0)),
+            vec3(0.0, 2.0, 0.0),
+        );
+        assert_vec(
+            rotate(xr::Quaternionf::IDENTITY, vec3(1.0, 2.0, 3.0)),
+            vec3(1.0, 2.0, 3.0),
+        );
+        assert_vec(
+            forward(pose(yaw(FRAC_PI_2), vec3(5.0, 0.0, 0.0))),
+            vec3(-1.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn multiplied_quaternions_rotate_right_to_left() {
+        let (a, b) = (skewed().orientation, yaw(0.7));
+        let v = vec3(0.2, -1.0, 0.5);
+        assert_vec(rotate(mul_quat(a, b), v), rotate(a, rotate(b, v)));
+        assert_vec(rotate(conjugate(a), rotate(a, v)), v);
+    }
+
+    #[test]
+    fn composes_relative_poses() {
+        // One meter ahead of something standing at +X and facing -X is the origin.
+        let parent = pose(yaw(FRAC_PI_2), vec3(1.0, 0.0, 0.0));
+        let child = pose(yaw(FRAC_PI_2), vec3(0.0, 0.0, -1.0));
+        let composed = compose(parent, child);
+        assert_vec(composed.position, vec3(0.0, 0.0, 0.0));
+        assert_vec(
+            rotate(composed.orientation, vec3(1.0, 0.0, 0.0)),
+            vec3(-1.0, 0.0, 0.0),
+        );
+
+        let p = vec3(0.3, 0.6, -0.9);
+        assert_vec(
+            compose(skewed(), pose(xr::Quaternionf::IDENTITY, p)).position,
+            add(skewed().position, rotate(skewed().orientation, p)),
+        );
+    }
+
+    #[test]
+    fn inverse_undoes_a_pose() {
+        assert_identity(compose(inverse(skewed()), skewed()));
+        assert_identity(compose(skewed(), inverse(skewed())));
+        assert_identity(inverse(pose(
+            xr::Quaternionf::IDENTITY,
+            vec3(0.0, 0.0, 0.0),
+        )));
+    }
+
+    #[test]
+    fn pose_matrix_matches_compose() {
+        let p = vec3(-0.4, 1.1, 2.0);
+        let moved = compose(skewed(), pose(xr::Quaternionf::IDENTITY, p)).position;
+        assert_vec(transform(&pose_matrix(skewed()), p), moved);
+        let back = mul_mat4(&pose_matrix(inverse(skewed())), &pose_matrix(skewed()));
+        assert_vec(transform(&back, p), p);
+    }
+
+    #[test]
+    fn projects_into_vulkan_clip_space() {
+        let fov = xr::Fovf {
+            angle_left: -0.8,
+            angle_right: 0.6,
+            angle_up: 0.7,
+            angle_down: -0.5,
+        };
+        let (near, far) = (0.1, 50.0);
+        let m = projection(fov, near, far);
+        let at = |depth: f32, x: f32, y: f32| vec3(x * depth, y * depth, -depth);
+
+        // The frustum's edges land on the edges of clip space, +Y pointing down.
+        let left = fov.angle_left.tan();
+        let right = fov.angle_right.tan();
+        let up = fov.angle_up.tan();
+        let down = fov.angle_down.tan();
+        assert_vec(
+            transform(&m, at(1.0, left, up)),
+            vec3(-1.0, -1.0, transform(&m, at(1.0, 0.0, 0.0)).z),
+        );
+        assert_vec(
+            transform(&m, at(7.0, right, down)),
+            vec3(1.0, 1.0, transform(&m, at(7.0, 0.0, 0.0)).z),
+        );
+
+        // Depth runs from 0 at the near plane to 1 at the far one.
+        assert!(transform(&m, at(near, 0.0, 0.0)).z.abs() < 1e-5);
+        assert!((transform(&m, at(far, 0.0, 0.0)).z - 1.0).abs() < 1e-4);
+        let (mid, farther) = (
+            transform(&m, at(1.0, 0.0, 0.0)).z,
+            transform(&m, at(2.0, 0.0, 0.0)).z,
+        );
+        assert!(0.0 < mid && mid < farther && farther < 1.0);
+    }
+}
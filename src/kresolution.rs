@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use openxr as xr;
+
+/// Adjusts the render scale from measured GPU frame times so rendering keeps up with the
+/// display.
+///
+/// Feed it `CommandRing::gpu_time` and the frame state's predicted display period every frame
+/// and write the returned scale to `RenderConfig::render_scale`; `kaleido::run` does this for
+/// the controller `App::resolution_controller` returns. The scale only moves in `step`
+/// increments and at most once every `cooldown` frames, since each change recreates the
+/// swapchain.
+#[derive(Debug, Clone)]
+pub struct ResolutionController {
+    /// Smallest scale the controller will go down to.
+    pub min_scale: f32,
+    /// Largest scale the controller will go up to.
+    pub max_scale: f32,
+    /// Fraction of the display period the GPU may spend on a frame before the scale drops.
+    pub budget: f32,
+    /// Amount the scale changes by at a time.
+    pub step: f32,
+    /// Frames to measure after a change before changing again.
+    pub cooldown: u32,
+    scale: f32,
+    /// Smoothed GPU time in seconds since the last change.
+    average: Option<f32>,
+    frames: u32,
+}
+
+impl Default for ResolutionController {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl ResolutionController {
+    pub fn new(scale: f32) -> Self {
+        Self {
+            min_scale: 0.5,
+            max_scale: 1.0,
+            budget: 0.85,
+            step: 0.05,
+            cooldown: 30,
+            scale,
+            average: None,
+            frames: 0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Records one frame's GPU time and returns the scale to render the next frames at.
+    pub fn update(&mut self, gpu_time: Duration, display_period: xr::Duration) -> f32 {
+        let sample = gpu_time.as_secs_f32();
+        let average = match self.average {
+            Some(average) => average + (sample - average) * 0.1,
+            None => sample,
+        };
+        self.average = Some(average);
+        self.frames += 1;
+        if self.frames < self.cooldown {
+            return self.scale;
+        }
+
+        // GPU cost grows with the pixel count, so with the square of the scale.
+        let budget = display_period.as_nanos() as f32 * 1e-9 * self.budget;
+        let up = (self.scale + self.step).min(self.max_scale);
+        let cost = |scale: f32| average * (scale / self.scale).powi(2);
+        let scale = if average > budget {
+            (self.scale - self.step).max(self.min_scale)
+        } else if cost(up) < budget {
+            up
+        } else {
+            self.scale
+        };
+
+        if scale != self.scale {
+            // Timings taken at the old size say nothing about the new one.
+            self.scale = scale;
+            self.average = None;
+            self.frames = 0;
+        }
+        self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The display period of a 90 Hz headset, which leaves a budget of about 9.4 ms.
+    const PERIOD_NANOS: i64 = 11_111_111;
+
+    /// Feeds `frames` frames that each took `millis` of GPU time and returns the last scale.
+    fn feed(controller: &mut ResolutionController, millis: f32, frames: u32) -> f32 {
+        let gpu_time = Duration::from_secs_f32(millis / 1000.0);
+        let period = xr::Duration::from_nanos(PERIOD_NANOS);
+        (0..frames)
+            .map(|_| controller.update(gpu_time, period))
+            .last()
+            .unwrap_or(controller.scale())
+    }
+
+    fn assert_scale(scale: f32, expected: f32) {
+        assert!(
+            (scale - expected).abs() < 1e-5,
+            "expected {}, got {}",
+            expected,
+            scale
+        );
+    }
+
+    #[test]
+    fn waits_out_the_cooldown_before_changing() {
+        let mut controller = ResolutionController::new(1.0);
+        assert_scale(feed(&mut controller, 20.0, 29), 1.0);
+        assert_scale(feed(&mut controller, 20.0, 1), 0.95);
+        // The change starts a new cooldown.
+        assert_scale(feed(&mut controller, 20.0, 29), 0.95);
+        assert_scale(feed(&mut controller, 20.0, 1), 0.9);
+    }
+
+    #[test]
+    fn steps_down_to_the_minimum() {
+        let mut controller = ResolutionController::new(0.6);
+        assert_scale(feed(&mut controller, 20.0, 30), 0.55);
+        assert_scale(feed(&mut controller, 20.0, 30), 0.5);
+        assert_scale(feed(&mut controller, 20.0, 300), 0.5);
+    }
+
+    #[test]
+    fn steps_up_to_the_maximum() {
+        let mut controller = ResolutionController::new(0.9);
+        assert_scale(feed(&mut controller, 2.0, 30), 0.95);
+        assert_scale(feed(&mut controller, 2.0, 30), 1.0);
+        assert_scale(feed(&mut controller, 2.0, 300), 1.0);
+    }
+
+    #[test]
+    fn holds_when_the_next_step_up_would_blow_the_budget() {
+        // 9 ms fits the 9.4 ms budget at 0.8, but 0.85 would cost 9 * (0.85 / 0.8)^2 = 10.2 ms.
+        let mut controller = ResolutionController::new(0.8);
+        assert_scale(feed(&mut controller, 9.0, 300), 0.8);
+        // While 8 ms leaves enough room, costing 9 ms at 0.85.
+        assert_scale(feed(&mut controller, 8.0, 30), 0.85);
+    }
+
+    #[test]
+    fn a_single_slow_frame_is_smoothed_out() {
+        let mut controller = ResolutionController::new(1.0);
+        feed(&mut controller, 5.0, 29);
+        assert_scale(feed(&mut controller, 30.0, 1), 1.0);
+    }
+
+    #[test]
+    fn honors_custom_limits() {
+        let mut controller = ResolutionController::new(1.0);
+        controller.min_scale = 0.8;
+        controller.step = 0.1;
+        controller.cooldown = 5;
+        assert_scale(feed(&mut controller, 20.0, 5), 0.9);
+        assert_scale(feed(&mut controller, 20.0, 5), 0.8);
+        assert_scale(feed(&mut controller, 20.0, 50), 0.8);
+    }
+}
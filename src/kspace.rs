@@ -0,0 +1,352 @@
+use openxr as xr;
+
+use crate::kerror::Result;
+use crate::kmath::{compose, conjugate, forward, inverse, rotate, sub, vec3};
+
+/// Which reference space `kaleido::run` locates views in and submits layers in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaceConfig {
+    /// Space types in order of preference. The first one the runtime offers is used; LOCAL,
+    /// which every runtime has, is the last resort.
+    pub preferred: Vec<xr::ReferenceSpaceType>,
+    /// Where the space's origin sits relative to the runtime's, e.g. to raise a seated LOCAL
+    /// space to standing height.
+    pub offset: xr::Posef,
+}
+
+impl Default for SpaceConfig {
+    fn default() -> Self {
+        Self {
+            preferred: vec![
+                xr::ReferenceSpaceType::STAGE,
+                xr::ReferenceSpaceType::LOCAL_FLOOR,
+                xr::ReferenceSpaceType::LOCAL,
+            ],
+            offset: xr::Posef::IDENTITY,
+        }
+    }
+}
+
+impl SpaceConfig {
+    pub fn new(preferred: &[xr::ReferenceSpaceType]) -> Self {
+        Self {
+            preferred: preferred.to_vec(),
+            ..Self::default()
+        }
+    }
+
+    pub fn offset(mut self, offset: xr::Posef) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+/// The reference space picked from a [`SpaceConfig`], with its offset applied.
+pub struct ReferenceSpace {
+    session: xr::Session<xr::Vulkan>,
+    ty: xr::ReferenceSpaceType,
+    offset: xr::Posef,
+    space: xr::Space,
+    view: xr::Space,
+    bounds: Option<xr::Extent2Df>,
+}
+
+impl ReferenceSpace {
+    pub fn new(session: &xr::Session<xr::Vulkan>, config: &SpaceConfig) -> Result<Self> {
+        let available = session.enumerate_reference_spaces()?;
+        let ty = match config.preferred.iter().find(|ty| available.contains(ty)) {
+            Some(ty) => *ty,
+            None => {
+                log::warn!(
+                    "none of the preferred reference spaces {:?} are available, using LOCAL",
+                    config.preferred
+                );
+                xr::ReferenceSpaceType::LOCAL
+            }
+        };
+        if config.preferred.first() != Some(&ty) {
+            log::info!("falling back to the {:?} reference space", ty);
+        }
+        let mut space = Self {
+            session: session.clone(),
+            ty,
+            offset: config.offset,
+            space: session.create_reference_space(ty, config.offset)?,
+            view: session
+                .create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?,
+            bounds: None,
+        };
+        space.refresh_bounds()?;
+        Ok(space)
+    }
+
+    pub fn space(&self) -> &xr::Space {
+        &self.space
+    }
+
+    /// The type of the space the runtime tracks, which may be a fallback from the preferred one.
+    pub fn ty(&self) -> xr::ReferenceSpaceType {
+        self.ty
+    }
+
+    pub fn offset(&self) -> xr::Posef {
+        self.offset
+    }
+
+    /// The play area as of the last refresh, or `None` if the runtime doesn't know one for this
+    /// space type; usually only STAGE has bounds.
+    pub fn play_area(&self) -> Option<PlayArea> {
+        self.bounds.map(|extent| PlayArea {
+            extent,
+            pose: inverse(self.offset),
+        })
+    }
+
+    /// Asks the runtime for the play area's size again. `kaleido::run` does this whenever the
+    /// runtime announces a change to the space, since that's when the user may have redrawn the
+    /// boundary.
+    pub fn refresh_bounds(&mut self) -> Result<()> {
+        let bounds = self.session.reference_space_bounds_rect(self.ty)?;
+        if bounds != self.bounds {
+            match bounds {
+                Some(extent) => log::info!(
+                    "{:?} play area is {:.2} x {:.2} m",
+                    self.ty,
+                    extent.width,
+                    extent.height
+                ),
+                None => log::info!("{:?} play area bounds unavailable", self.ty),
+            }
+        }
+        self.bounds = bounds;
+        Ok(())
+    }
+
+    /// Moves the space's origin to `offset` relative to the runtime's origin.
+    pub fn set_offset(&mut self, offset: xr::Posef) -> Result<()> {
+        self.space = self.session.create_reference_space(self.ty, offset)?;
+        self.offset = offset;
+        Ok(())
+    }
+
+    /// Moves the origin under the user's head, at the floor height of the current origin, and
+    /// turns it to face where the user is looking. Returns the change, or `None` if the head
+    /// isn't tracked at `time`.
+    pub fn recenter(&mut self, time: xr::Time) -> Result<Option<SpaceChange>> {
+        let head = self.view.locate(&self.space, time)?;
+        let valid =
+            xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID;
+        if !head.location_flags.contains(valid) {
+            return Ok(None);
+        }
+
+        // Only the heading counts; tilting the head shouldn't tilt the world.
+        let look = forward(head.pose);
+        let yaw = (-look.x).atan2(-look.z);
+        let new_origin = xr::Posef {
+            orientation: xr::Quaternionf {
+                x: 0.0,
+                y: (yaw / 2.0).sin(),
+                z: 0.0,
+                w: (yaw / 2.0).cos(),
+            },
+            position: vec3(head.pose.position.x, 0.0, head.pose.position.z),
+        };
+        self.set_offset(compose(self.offset, new_origin))?;
+        Ok(Some(SpaceChange {
+            ty: self.ty,
+            change_time: time,
+            pose_in_previous_space: Some(new_origin),
+        }))
+    }
+
+    /// Turns a `ReferenceSpaceChangePending` event for this space's type into the change it
+    /// makes to this space, offset included. Events for other space types give `None`.
+    pub fn change(&self, event: xr::ReferenceSpaceChangePending<'_>) -> Option<SpaceChange> {
+        if event.reference_space_type() != self.ty {
+            return None;
+        }
+        // The runtime reports where its new origin is in its old one; with an offset on top,
+        // the app's origin moves by the same amount seen from the offset.
+        let pose_in_previous_space = event.pose_valid().then(|| {
+            compose(
+                inverse(self.offset),
+                compose(event.pose_in_previous_space(), self.offset),
+            )
+        });
+        Some(SpaceChange {
+            ty: self.ty,
+            change_time: event.change_time(),
+            pose_in_previous_space,
+        })
+    }
+}
+
+/// The rectangle the user can move around in, as reported by `xrGetReferenceSpaceBoundsRect`.
+///
+/// The runtime only gives the size; the rectangle is centered on its own origin of the space and
+/// aligned with its axes, which after an offset or recentering is `pose` in the app's space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayArea {
+    /// Size along the area's X axis (`width`) and Z axis (`height`), in meters.
+    pub extent: xr::Extent2Df,
+    /// The area's center and orientation in the reference space. +Y is up.
+    pub pose: xr::Posef,
+}
+
+impl PlayArea {
+    /// How far `point`, given in the reference space, is from the nearest edge when seen from
+    /// above. Negative once the point is outside the area.
+    pub fn distance_to_edge(&self, point: xr::Vector3f) -> f32 {
+        let local = rotate(
+            conjugate(self.pose.orientation),
+            sub(point, self.pose.position),
+        );
+        (self.extent.width / 2.0 - local.x.abs()).min(self.extent.height / 2.0 - local.z.abs())
+    }
+
+    /// Whether `point` is inside the area when seen from above.
+    pub fn contains(&self, point: xr::Vector3f) -> bool {
+        self.distance_to_edge(point) >= 0.0
+    }
+
+    /// The corners on the floor in the reference space, going around the edge.
+    pub fn corners(&self) -> [xr::Vector3f; 4] {
+        let (x, z) = (self.extent.width / 2.0, self.extent.height / 2.0);
+        [(-x, -z), (x, -z), (x, z), (-x, z)].map(|(x, z)| {
+            compose(
+                self.pose,
+                xr::Posef {
+                    orientation: xr::Quaternionf::IDENTITY,
+                    position: vec3(x, 0.0, z),
+                },
+            )
+            .position
+        })
+    }
+}
+
+/// The origin of the reference space moved, through recentering or the runtime's own doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpaceChange {
+    pub ty: xr::ReferenceSpaceType,
+    /// When the new origin takes effect. Poses located for earlier times are still relative to
+    /// the old one.
+    pub change_time: xr::Time,
+    /// Where the new origin is in the old space, if known.
+    pub pose_in_previous_space: Option<xr::Posef>,
+}
+
+impl SpaceChange {
+    /// Re-expresses `pose`, which was relative to the old origin, relative to the new one, so
+    /// world-locked content stays put. Unknown changes leave it as it is.
+    pub fn apply(&self, pose: xr::Posef) -> xr::Posef {
+        match self.pose_in_previous_space {
+            Some(new_origin) => compose(inverse(new_origin), pose),
+            None => pose,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::kmath::distance;
+
+    /// A 4 x 2 m area whose center is at `position`, turned `yaw` radians to the left.
+    fn area(yaw: f32, position: xr::Vector3f) -> PlayArea {
+        PlayArea {
+            extent: xr::Extent2Df {
+                width: 4.0,
+                height: 2.0,
+            },
+            pose: xr::Posef {
+                orientation: xr::Quaternionf {
+                    x: 0.0,
+                    y: (yaw / 2.0).sin(),
+                    z: 0.0,
+                    w: (yaw / 2.0).cos(),
+                },
+                position,
+            },
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn distance_to_the_nearest_edge() {
+        let area = area(0.0, vec3(0.0, 0.0, 0.0));
+        assert_close(area.distance_to_edge(vec3(0.0, 0.0, 0.0)), 1.0);
+        assert_close(area.distance_to_edge(vec3(1.5, 0.0, 0.0)), 0.5);
+        assert_close(area.distance_to_edge(vec3(-1.0, 0.0, 0.8)), 0.2);
+        // Only the floor plan counts, not the height.
+        assert_close(area.distance_to_edge(vec3(1.5, 1.7, 0.0)), 0.5);
+        assert!(area.contains(vec3(2.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn distance_is_negative_outside() {
+        let area = area(0.0, vec3(0.0, 0.0, 0.0));
+        assert_close(area.distance_to_edge(vec3(3.0, 0.0, 0.0)), -1.0);
+        assert_close(area.distance_to_edge(vec3(0.0, 0.0, -1.5)), -0.5);
+        assert!(!area.contains(vec3(0.0, 0.0, -1.5)));
+    }
+
+    #[test]
+    fn distance_follows_the_area_pose() {
+        // Turned a quarter, the 4 m side runs along Z.
+        let area = area(FRAC_PI_2, vec3(10.0, 0.0, 0.0));
+        assert_close(area.distance_to_edge(vec3(10.0, 0.0, 1.5)), 0.5);
+        assert_close(area.distance_to_edge(vec3(10.5, 0.0, 0.0)), 0.5);
+        assert!(!area.contains(vec3(11.5, 0.0, 0.0)));
+        assert!(!area.contains(vec3(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn corners_go_around_the_edge() {
+        let corners = area(FRAC_PI_2, vec3(10.0, 0.0, 0.0)).corners();
+        let expected = [
+            vec3(9.0, 0.0, 2.0),
+            vec3(9.0, 0.0, -2.0),
+            vec3(11.0, 0.0, -2.0),
+            vec3(11.0, 0.0, 2.0),
+        ];
+        for (corner, expected) in corners.into_iter().zip(expected) {
+            assert!(distance(corner, expected) < 1e-5, "{:?}", corner);
+        }
+    }
+
+    #[test]
+    fn space_changes_keep_world_locked_poses_in_place() {
+        // The origin moved one meter along +X, so what was at +X is now at the origin.
+        let change = SpaceChange {
+            ty: xr::ReferenceSpaceType::STAGE,
+            change_time: xr::Time::from_nanos(0),
+            pose_in_previous_space: Some(xr::Posef {
+                orientation: xr::Quaternionf::IDENTITY,
+                position: vec3(1.0, 0.0, 0.0),
+            }),
+        };
+        let pose = xr::Posef {
+            orientation: xr::Quaternionf::IDENTITY,
+            position: vec3(1.0, 1.5, -2.0),
+        };
+        assert!(distance(change.apply(pose).position, vec3(0.0, 1.5, -2.0)) < 1e-5);
+
+        let unknown = SpaceChange {
+            pose_in_previous_space: None,
+            ..change
+        };
+        assert_eq!(unknown.apply(pose), pose);
+    }
+}
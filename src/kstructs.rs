@@ -1,26 +1,625 @@
+use std::{sync::Arc, time::Duration};
 
-use std::{
-    io::Cursor,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
-};
+use ash::vk;
+use openxr as xr;
 
-use ash::{
-    util::read_spv,
-    vk::{self, Handle},
+use crate::kconstants::{
+    COLOR_FORMAT, COLOR_FORMAT_PREFERENCES, DEPTH_FORMAT_PREFERENCES, PIPELINE_DEPTH, VIEW_COUNT,
+    VIEW_TYPE,
 };
-use openxr as xr;
+use crate::kerror::{KaleidoError, Result};
+
+/// Renderer settings chosen at startup. The defaults are the values in `kconstants`.
+#[derive(Clone)]
+pub struct RenderConfig {
+    /// Format of the swapchain images and the render pass color attachment. Overwritten with the
+    /// result of negotiating `color_formats` with the runtime when the context is built.
+    pub color_format: vk::Format,
+    /// Acceptable swapchain formats in order of preference.
+    pub color_formats: Vec<vk::Format>,
+    /// Number of views the view configuration has, e.g. 2 for PRIMARY_STEREO.
+    pub view_count: u32,
+    pub view_type: xr::ViewConfigurationType,
+    /// Whether all views are drawn in one multiview pass or one pass per view.
+    pub view_mode: ViewMode,
+    /// Maximum number of frames in flight.
+    pub pipeline_depth: u32,
+    /// Factor applied to the runtime's recommended view size. Can be changed between frames;
+    /// `create_swapchain` rebuilds the swapchain when it no longer matches.
+    pub render_scale: f32,
+    /// Where the depth attachment comes from, or `None` to render without depth.
+    pub depth: Option<DepthSource>,
+    /// Format of the depth attachment. Overwritten with the result of negotiating
+    /// `depth_formats` when the context is built with depth enabled.
+    pub depth_format: vk::Format,
+    /// Acceptable depth formats in order of preference.
+    pub depth_formats: Vec<vk::Format>,
+    /// Requested MSAA sample count. Clamped to what the device supports when the context is
+    /// built; anything above 1 renders into transient multisampled attachments that are resolved
+    /// into the swapchain image.
+    pub samples: u32,
+    /// When set, depth is handed to the compositor through XR_KHR_composition_layer_depth so it
+    /// can reproject positionally. This forces `DepthSource::XrSwapchain`, and is cleared again
+    /// when the context is built if the runtime lacks the extension.
+    pub submit_depth: Option<DepthRange>,
+}
+
+/// Near and far planes of the projection used to render, in meters.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthRange {
+    pub near: f32,
+    pub far: f32,
+}
+
+/// How the views of a frame are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewMode {
+    /// A single render pass draws every view at once through VK_KHR_multiview.
+    Multiview,
+    /// The render pass is begun once per view, each time targeting that view's image-array
+    /// layer. Works without multiview support and lets the two paths be compared.
+    PerView,
+}
+
+/// Where the depth attachment's images come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSource {
+    /// Device-local images kaleido allocates itself, one per framebuffer. Depth is discarded at
+    /// the end of the render pass.
+    Owned,
+    /// A depth swapchain from the runtime, so the depth can be handed to the compositor.
+    XrSwapchain,
+}
+
+impl RenderConfig {
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        vk::SampleCountFlags::from_raw(self.samples)
+    }
+
+    pub fn multisampled(&self) -> bool {
+        self.samples > 1
+    }
+
+    /// Number of render pass instances, and so framebuffers, per swapchain image.
+    pub fn passes(&self) -> u32 {
+        match self.view_mode {
+            ViewMode::Multiview => 1,
+            ViewMode::PerView => self.view_count,
+        }
+    }
+
+    /// Clear values matching the attachments of the render pass built from this config.
+    pub fn clear_values(&self, color: [f32; 4]) -> Vec<vk::ClearValue> {
+        let mut values = vec![vk::ClearValue {
+            color: vk::ClearColorValue { float32: color },
+        }];
+        if self.depth.is_some() {
+            values.push(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+        }
+        values
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            color_format: COLOR_FORMAT,
+            color_formats: COLOR_FORMAT_PREFERENCES.to_vec(),
+            view_count: VIEW_COUNT,
+            view_type: VIEW_TYPE,
+            view_mode: ViewMode::Multiview,
+            pipeline_depth: PIPELINE_DEPTH,
+            render_scale: 1.0,
+            depth: None,
+            depth_format: DEPTH_FORMAT_PREFERENCES[0],
+            depth_formats: DEPTH_FORMAT_PREFERENCES.to_vec(),
+            samples: 1,
+            submit_depth: None,
+        }
+    }
+}
+
+/// The Vulkan instance and device the OpenXR runtime asked us to create.
+///
+/// Every other Vulkan wrapper holds an `Arc` to this, so the device is only destroyed once nothing
+/// can use it anymore. The OpenXR session doesn't hold one, so it must be dropped first.
+pub struct DeviceContext {
+    pub instance: ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: ash::Device,
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+    // Keeps the Vulkan library loaded for as long as the function pointers above are in use.
+    _entry: ash::Entry,
+}
+
+impl DeviceContext {
+    pub(crate) fn new(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queue: vk::Queue,
+        queue_family_index: u32,
+    ) -> Self {
+        Self {
+            instance,
+            physical_device,
+            device,
+            queue,
+            queue_family_index,
+            _entry: entry,
+        }
+    }
+
+    /// Blocks until the device has finished all submitted work.
+    pub fn wait_idle(&self) -> Result<()> {
+        unsafe { self.device.device_wait_idle() }?;
+        Ok(())
+    }
+}
+
+impl Drop for DeviceContext {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// A render pass owned by the device it was created on.
+pub struct RenderPass {
+    pub handle: vk::RenderPass,
+    device: Arc<DeviceContext>,
+}
+
+impl RenderPass {
+    pub(crate) fn new(device: Arc<DeviceContext>, handle: vk::RenderPass) -> Self {
+        Self { handle, device }
+    }
+}
+
+impl Drop for RenderPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_render_pass(self.handle, None);
+        }
+    }
+}
+
+/// A graphics pipeline together with its layout.
+pub struct Pipeline {
+    pub handle: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    device: Arc<DeviceContext>,
+}
+
+impl Pipeline {
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        handle: vk::Pipeline,
+        layout: vk::PipelineLayout,
+    ) -> Self {
+        Self {
+            handle,
+            layout,
+            device,
+        }
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device.device_wait_idle();
+            self.device.device.destroy_pipeline(self.handle, None);
+            self.device
+                .device
+                .destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// One command buffer and fence per frame in flight, handed out round-robin.
+///
+/// When the queue supports timestamps the ring also keeps a pair of timestamp queries per frame,
+/// which `begin_timing` and `end_timing` write and `acquire` reads back once the frame is done.
+pub struct CommandRing {
+    pub pool: vk::CommandPool,
+    pub cmds: Vec<vk::CommandBuffer>,
+    pub fences: Vec<vk::Fence>,
+    pub(crate) queries: Option<vk::QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    pub(crate) timestamp_period: f32,
+    /// Mask of the timestamp bits the queue actually writes.
+    pub(crate) timestamp_mask: u64,
+    timed: Vec<bool>,
+    gpu_time: Option<Duration>,
+    frame: usize,
+    device: Arc<DeviceContext>,
+}
 
-pub(crate) struct Swapchain {
+impl CommandRing {
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        pool: vk::CommandPool,
+        cmds: Vec<vk::CommandBuffer>,
+        fences: Vec<vk::Fence>,
+    ) -> Self {
+        Self {
+            pool,
+            timed: vec![false; cmds.len()],
+            cmds,
+            fences,
+            queries: None,
+            timestamp_period: 0.0,
+            timestamp_mask: 0,
+            gpu_time: None,
+            frame: 0,
+            device,
+        }
+    }
+
+    /// Waits until the current slot's previous submission has finished, then hands out its
+    /// command buffer for reuse.
+    pub fn acquire(&mut self) -> Result<vk::CommandBuffer> {
+        let fence = self.fences[self.frame];
+        unsafe {
+            self.device
+                .device
+                .wait_for_fences(&[fence], true, u64::MAX)?;
+        }
+        if let (Some(queries), true) = (self.queries, self.timed[self.frame]) {
+            self.timed[self.frame] = false;
+            let mut timestamps = [0u64; 2];
+            unsafe {
+                self.device.device.get_query_pool_results(
+                    queries,
+                    self.frame as u32 * 2,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )?;
+            }
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+            self.gpu_time = Some(Duration::from_nanos(
+                (ticks as f64 * self.timestamp_period as f64) as u64,
+            ));
+        }
+        Ok(self.cmds[self.frame])
+    }
+
+    /// Submits the current slot's command buffer to the device queue.
+    ///
+    /// The fence is only reset here, so a frame abandoned between `acquire` and `submit` leaves
+    /// it signaled and nothing waits on it forever.
+    pub fn submit(&mut self) -> Result<()> {
+        let fence = self.fences[self.frame];
+        unsafe {
+            self.device.device.reset_fences(&[fence])?;
+            self.device.device.queue_submit(
+                self.device.queue,
+                &[vk::SubmitInfo::default()
+                    .command_buffers(&self.cmds[self.frame..self.frame + 1])],
+                fence,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Starts measuring GPU time for the frame being recorded into `cmd`. Must be recorded
+    /// outside a render pass. Does nothing when the queue can't write timestamps.
+    pub fn begin_timing(&self, cmd: vk::CommandBuffer) {
+        let Some(queries) = self.queries else {
+            return;
+        };
+        let first = self.frame as u32 * 2;
+        unsafe {
+            self.device
+                .device
+                .cmd_reset_query_pool(cmd, queries, first, 2);
+            self.device.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                queries,
+                first,
+            );
+        }
+    }
+
+    /// Stops the measurement started by `begin_timing`. The result shows up in `gpu_time` once
+    /// this slot is acquired again.
+    pub fn end_timing(&mut self, cmd: vk::CommandBuffer) {
+        let Some(queries) = self.queries else {
+            return;
+        };
+        unsafe {
+            self.device.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                queries,
+                self.frame as u32 * 2 + 1,
+            );
+        }
+        self.timed[self.frame] = true;
+    }
+
+    /// GPU time of the most recently completed timed frame, if any.
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.gpu_time
+    }
+
+    /// Forgets the timings of every frame still in flight and the last one read back, e.g. once
+    /// a resolution change has made them unrepresentative. `gpu_time` stays `None` until a frame
+    /// recorded after this call completes.
+    pub fn discard_timings(&mut self) {
+        self.timed.fill(false);
+        self.gpu_time = None;
+    }
+
+    /// Index of the current frame in flight.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Moves on to the next frame in flight.
+    pub fn advance(&mut self) {
+        self.frame = (self.frame + 1) % self.cmds.len();
+    }
+}
+
+impl Drop for CommandRing {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device.wait_for_fences(&self.fences, true, !0);
+            for &fence in &self.fences {
+                self.device.device.destroy_fence(fence, None);
+            }
+            if let Some(queries) = self.queries {
+                self.device.device.destroy_query_pool(queries, None);
+            }
+            self.device.device.destroy_command_pool(self.pool, None);
+        }
+    }
+}
+
+/// An XR swapchain together with the framebuffers rendering into each of its images.
+pub struct Swapchain {
+    // Declared before the handles so the views are gone before the images they point at.
+    /// Framebuffers for every image, `passes` consecutive entries per image.
+    pub buffers: Vec<Framebuffer>,
+    pub passes: usize,
     pub handle: xr::Swapchain<xr::Vulkan>,
-    pub buffers: Vec<crate::Framebuffer>,
+    /// The runtime-provided depth swapchain when rendering with `DepthSource::XrSwapchain`.
+    pub depth_handle: Option<xr::Swapchain<xr::Vulkan>>,
+    /// Size of the swapchain images, large enough for every view.
     pub resolution: vk::Extent2D,
+    /// The area each view renders to, anchored at the top-left corner of its layer.
+    pub view_extents: Vec<vk::Extent2D>,
+    /// The `RenderConfig::render_scale` the swapchain was created at.
+    pub scale: f32,
+    device: Arc<DeviceContext>,
+}
+
+impl Swapchain {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        handle: xr::Swapchain<xr::Vulkan>,
+        depth_handle: Option<xr::Swapchain<xr::Vulkan>>,
+        buffers: Vec<Framebuffer>,
+        passes: usize,
+        resolution: vk::Extent2D,
+        view_extents: Vec<vk::Extent2D>,
+        scale: f32,
+    ) -> Self {
+        Self {
+            buffers,
+            passes,
+            handle,
+            depth_handle,
+            resolution,
+            view_extents,
+            scale,
+            device,
+        }
+    }
+
+    /// The framebuffers rendering into image `index`, one per render pass instance.
+    pub fn framebuffers(&self, index: usize) -> &[Framebuffer] {
+        &self.buffers[index * self.passes..(index + 1) * self.passes]
+    }
+
+    /// The part of its layer `view` renders to, as submitted to the compositor.
+    pub fn image_rect(&self, view: usize) -> xr::Rect2Di {
+        let extent = self.view_extents[view];
+        xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: extent.width as _,
+                height: extent.height as _,
+            },
+        }
+    }
+
+    /// Acquires the next image and returns its index.
+    ///
+    /// Color and depth images are always acquired and released together, which keeps the two
+    /// swapchains in lockstep so a single index addresses both.
+    pub fn acquire(&mut self) -> Result<usize> {
+        let index = self.handle.acquire_image()?;
+        if let Some(depth) = &mut self.depth_handle {
+            let depth_index = depth.acquire_image()?;
+            if depth_index != index {
+                return Err(KaleidoError::SwapchainOutOfSync);
+            }
+        }
+        Ok(index as usize)
+    }
+
+    /// Waits until the images acquired by `acquire` can be written to.
+    pub fn wait(&mut self) -> Result<()> {
+        self.handle.wait_image(xr::Duration::INFINITE)?;
+        if let Some(depth) = &mut self.depth_handle {
+            depth.wait_image(xr::Duration::INFINITE)?;
+        }
+        Ok(())
+    }
+
+    /// Hands the images acquired by `acquire` back to the runtime.
+    pub fn release(&mut self) -> Result<()> {
+        self.handle.release_image()?;
+        if let Some(depth) = &mut self.depth_handle {
+            depth.release_image()?;
+        }
+        Ok(())
+    }
 }
 
-pub(crate) struct Framebuffer {
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        // The images may still be in use by a frame in flight.
+        let _ = self.device.wait_idle();
+    }
+}
+
+/// The framebuffer and attachment views for a single swapchain image.
+pub struct Framebuffer {
     pub framebuffer: vk::Framebuffer,
     pub color: vk::ImageView,
+    pub depth: Option<vk::ImageView>,
+    /// Backing storage for `depth` when kaleido allocated it rather than the runtime.
+    pub depth_image: Option<Image>,
+    /// Multisampled color target resolved into `color` when rendering with MSAA.
+    pub msaa: Option<vk::ImageView>,
+    pub msaa_image: Option<Image>,
+    device: Arc<DeviceContext>,
+}
+
+impl Framebuffer {
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        framebuffer: vk::Framebuffer,
+        color: vk::ImageView,
+        depth: Option<vk::ImageView>,
+        depth_image: Option<Image>,
+    ) -> Self {
+        Self {
+            framebuffer,
+            color,
+            depth,
+            depth_image,
+            msaa: None,
+            msaa_image: None,
+            device,
+        }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .device
+                .destroy_framebuffer(self.framebuffer, None);
+            self.device.device.destroy_image_view(self.color, None);
+            if let Some(depth) = self.depth {
+                self.device.device.destroy_image_view(depth, None);
+            }
+            if let Some(msaa) = self.msaa {
+                self.device.device.destroy_image_view(msaa, None);
+            }
+        }
+    }
+}
+
+/// A device-local image and the memory bound to it.
+pub struct Image {
+    pub handle: vk::Image,
+    pub memory: vk::DeviceMemory,
+    device: Arc<DeviceContext>,
+}
+
+impl Image {
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        handle: vk::Image,
+        memory: vk::DeviceMemory,
+    ) -> Self {
+        Self {
+            handle,
+            memory,
+            device,
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_image(self.handle, None);
+            self.device.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// A buffer in host-visible, coherent memory that the CPU writes directly.
+pub struct Buffer {
+    pub handle: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    /// Size of the buffer in bytes.
+    pub size: vk::DeviceSize,
+    device: Arc<DeviceContext>,
+}
+
+impl Buffer {
+    pub(crate) fn new(
+        device: Arc<DeviceContext>,
+        handle: vk::Buffer,
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+    ) -> Self {
+        Self {
+            handle,
+            memory,
+            size,
+            device,
+        }
+    }
+
+    /// Copies `data` to the start of the buffer. The GPU must not be reading it meanwhile.
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        if data.len() as vk::DeviceSize > self.size {
+            return Err(KaleidoError::BufferOverflow {
+                size: self.size,
+                len: data.len() as vk::DeviceSize,
+            });
+        }
+        unsafe {
+            let mapped = self.device.device.map_memory(
+                self.memory,
+                0,
+                data.len() as vk::DeviceSize,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut u8, data.len());
+            self.device.device.unmap_memory(self.memory);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_buffer(self.handle, None);
+            self.device.device.free_memory(self.memory, None);
+        }
+    }
 }
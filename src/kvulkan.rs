@@ -1,272 +1,288 @@
 //! Illustrates rendering using Vulkan with multiview. Supports any Vulkan 1.1 capable environment.
 //!
-//! Renders a smooth gradient across the entire view, with different colors per eye.
+//! Renders a smooth gradient across the entire view, with different colors per eye, and the edges
+//! of the play area once the head or a controller gets close to them.
 //!
-//! This example uses minimal abstraction for clarity. Real-world code should encapsulate and
-//! largely decouple its Vulkan and OpenXR components and handle errors gracefully.
+//! The frame loop itself lives in `kaleido::run`; this example only supplies the pipeline, the
+//! input handling and the draw call through the `App` trait.
 
-use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
 };
 
-use ash::{
-    vk::{self, Handle},
-};
+use ash::vk;
 use openxr as xr;
 
-mod kabstract;
-use kabstract::*;
-
-mod kstructs;
-use kstructs::*;
-
-mod kconstants;
-use kconstants::*;
+use kaleido::kabstract::*;
+use kaleido::kapp::{FrameContext, RenderContext};
+use kaleido::kboundary::BoundaryGrid;
+use kaleido::kcontext::KaleidoContextBuilder;
+use kaleido::kerror::Result;
+use kaleido::kevents::EventBus;
+use kaleido::keyegaze::{Collider, EyeGaze, GazeTargets};
+use kaleido::kgestures::GestureRecognizer;
+use kaleido::khands::HandTracking;
+use kaleido::khaptics::{HapticPattern, HapticSequencer};
+use kaleido::kinput::{ControllerInput, Hand};
+use kaleido::klifecycle::SessionLifecycle;
+use kaleido::kresolution::ResolutionController;
+use kaleido::kspace::SpaceChange;
+use kaleido::kstructs::{Pipeline, RenderPass, ViewMode};
+use kaleido::{App, KaleidoContext};
 
-#[allow(clippy::field_reassign_with_default)] // False positive, might be fixed 1.51
-#[cfg_attr(target_os = "android", ndk_glue::main)]
-#[allow(clippy::field_reassign_with_default)]
 #[cfg_attr(target_os = "android", ndk_glue::main)]
 pub fn main() {
-
     // Handle interrupts gracefully
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
     ctrlc::set_handler(move || {
         r.store(false, Ordering::Relaxed);
     })
-        .expect("setting Ctrl-C handler");
+    .expect("setting Ctrl-C handler");
 
-    let (xr_instance, system, environment_blend_mode) = init_openxr();
+    if let Err(e) = kaleido::run(Kvulkan::new(running)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("exiting cleanly");
+}
 
-    let vk_target_version = vk::make_api_version(0, 1, 1, 0);
-    let vk_target_version_xr = xr::Version::new(1, 1, 0);
-    let reqs = xr_instance
-        .graphics_requirements::<xr::Vulkan>(system)
-        .unwrap();
+struct Kvulkan {
+    running: Arc<AtomicBool>,
+    profile_changed: Arc<AtomicBool>,
+    input: Option<ControllerInput>,
+    recenter: bool,
+    gaze: Option<EyeGaze>,
+    gaze_targets: GazeTargets,
+    looking_at: Option<u64>,
+    hands: Option<HandTracking>,
+    gestures: [GestureRecognizer; 2],
+    haptics: Option<HapticSequencer>,
+    boundary: Option<BoundaryGrid>,
+    pipeline: Option<Pipeline>,
+}
 
-    if vk_target_version_xr < reqs.min_api_version_supported
-        || vk_target_version_xr.major() > reqs.max_api_version_supported.major()
-    {
-        panic!(
-            "OpenXR runtime requires Vulkan version > {}, < {}.0.0",
-            reqs.min_api_version_supported,
-            reqs.max_api_version_supported.major() + 1
-        );
+impl Kvulkan {
+    fn new(running: Arc<AtomicBool>) -> Self {
+        Self {
+            running,
+            profile_changed: Arc::default(),
+            input: None,
+            recenter: false,
+            gaze: None,
+            gaze_targets: GazeTargets::new(),
+            looking_at: None,
+            hands: None,
+            gestures: Default::default(),
+            haptics: None,
+            boundary: None,
+            pipeline: None,
+        }
     }
+}
 
-    let (vk_instance, vk_physical_device, vk_device, queue, queue_family_index) =
-        init_vulkan(&xr_instance, system, vk_target_version);
-
-    let render_pass = create_render_pass(&vk_device);
-    let (pipeline, pipeline_layout) = create_pipeline(&vk_device, render_pass);
+impl App for Kvulkan {
+    fn configure(&self, builder: KaleidoContextBuilder) -> KaleidoContextBuilder {
+        builder
+            .application("kvulkan", 0)
+            .engine("kaleido", 0)
+            // Tracks bare hands and eye gaze where the runtime can.
+            .optional_extensions(|available, enabled| {
+                enabled.ext_hand_tracking = available.ext_hand_tracking;
+                enabled.ext_eye_gaze_interaction = available.ext_eye_gaze_interaction;
+            })
+    }
 
-    let (session, mut frame_wait, mut frame_stream) = unsafe {
-        xr_instance.create_session::<xr::Vulkan>(
-            system,
-            &xr::vulkan::SessionCreateInfo {
-                instance: vk_instance.handle().as_raw() as _,
-                physical_device: vk_physical_device.as_raw() as _,
-                device: vk_device.handle().as_raw() as _,
-                queue_family_index,
-                queue_index: 0,
-            }
-        )
-    }.unwrap();
+    fn init(&mut self, ctx: &KaleidoContext, render_pass: &RenderPass) -> Result<()> {
+        self.pipeline = Some(create_pipeline(
+            &ctx.device,
+            render_pass,
+            &ctx.render_config,
+        )?);
+        self.boundary = Some(BoundaryGrid::new(
+            &ctx.device,
+            render_pass,
+            &ctx.render_config,
+        )?);
+
+        // The gaze action set has to be attached in the same call as the controller actions.
+        self.gaze = EyeGaze::new(&ctx.instance, ctx.system, &ctx.session)?;
+        let others: Vec<_> = self.gaze.iter().map(|gaze| &gaze.set).collect();
+        self.input = Some(ControllerInput::with_action_sets(
+            &ctx.instance,
+            &ctx.session,
+            &others,
+        )?);
+        // A ball in front of the user, at eye height, to look at.
+        self.gaze_targets.insert(
+            0,
+            Collider::Sphere {
+                center: xr::Vector3f {
+                    x: 0.0,
+                    y: 1.5,
+                    z: -2.0,
+                },
+                radius: 0.3,
+            },
+        );
+        self.hands = Some(HandTracking::new(&ctx.instance, ctx.system, &ctx.session)?);
+        self.haptics = Some(HapticSequencer::new(&ctx.instance)?);
+        Ok(())
+    }
 
-    let (stage, action_set, left_action, right_action, left_space, right_space) = setup_openxr(&xr_instance, system, &session);
+    fn lifecycle(&mut self, lifecycle: &mut SessionLifecycle) {
+        lifecycle
+            .on_focused(|| println!("input focus gained"))
+            .on_unfocused(|| println!("input focus lost"));
+    }
 
-    let (cmd_pool, cmds, fences) = create_commands(&vk_device, queue_family_index);
+    fn events(&mut self, events: &mut EventBus) {
+        let changed = self.profile_changed.clone();
+        events.on_interaction_profile_changed(move |_| changed.store(true, Ordering::Relaxed));
+    }
 
-    // Main loop
-    let mut swapchain = None;
-    let mut event_storage = xr::EventDataBuffer::new();
-    let mut session_running = false;
-    let mut frame = 0;
-    'main_loop: loop {
-        if !running.load(Ordering::Relaxed) {
-            println!("requesting exit");
-            match session.request_exit() {
-                Ok(()) => {}
-                Err(xr::sys::Result::ERROR_SESSION_NOT_RUNNING) => break,
-                Err(e) => panic!("{}", e),
+    fn update(&mut self, frame: &FrameContext) -> Result<()> {
+        let head = frame.views.first().map(|view| view.pose.position);
+        // Without focus the runtime delivers no input, so there's nothing to print, and only the
+        // head can get close to the play area's edge.
+        let (Some(input), Some(hands), Some(haptics), true) = (
+            &self.input,
+            &self.hands,
+            &mut self.haptics,
+            frame.lifecycle.is_focused(),
+        ) else {
+            if let Some(boundary) = &mut self.boundary {
+                boundary.update(frame.play_area.as_ref(), head.as_slice());
             }
-        }
-
-        while let Some(event) = xr_instance.poll_event(&mut event_storage).unwrap() {
-            use xr::Event::*;
-            match event {
-                SessionStateChanged(e) => {
-                    // Session state change is where we can begin and end sessions, as well as
-                    // find quit messages!
-                    println!("entered state {:?}", e.state());
-                    match e.state() {
-                        xr::SessionState::READY => {
-                            session.begin(VIEW_TYPE).unwrap();
-                            session_running = true;
-                        }
-                        xr::SessionState::STOPPING => {
-                            session.end().unwrap();
-                            session_running = false;
-                        }
-                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
-                            break 'main_loop;
-                        }
-                        _ => {}
-                    }
-                }
-                InstanceLossPending(_) => {
-                    break 'main_loop;
-                }
-                EventsLost(e) => {
-                    println!("lost {} events", e.lost_event_count());
+            return Ok(());
+        };
+        // Syncs the gaze set too, since it was attached along with the controllers.
+        input.sync(frame.session)?;
+        if self.profile_changed.swap(false, Ordering::Relaxed) {
+            for hand in Hand::BOTH {
+                if let Some(profile) = input.active_profile(frame.session, hand)? {
+                    println!("{:?} hand is using {}", hand, profile.name);
                 }
-                _ => {}
             }
         }
-
-        if !session_running {
-            std::thread::sleep(Duration::from_millis(100));
-            continue;
-        }
-
-        let xr_frame_state = frame_wait.wait().unwrap();
-        frame_stream.begin().unwrap();
-
-        if !xr_frame_state.should_render {
-            frame_stream.end(xr_frame_state.predicted_display_time, environment_blend_mode, &[]).unwrap();
-            continue;
-        }
-
-        let swapchain = create_swapchain(&mut swapchain, &xr_instance, &vk_device, render_pass, system, &session);
-
-        let image_index = swapchain.handle.acquire_image().unwrap();
-
-            unsafe {
-                vk_device.wait_for_fences(&[fences[frame]], true, u64::MAX).unwrap();
-                vk_device.reset_fences(&[fences[frame]]).unwrap();
+        let snapshot = input.snapshot(frame.session, frame.space, frame.predicted_display_time)?;
+
+        if let Some(gaze) = &self.gaze {
+            let ray = gaze.ray(frame.session, frame.space, frame.predicted_display_time)?;
+            let looking_at = ray
+                .and_then(|ray| self.gaze_targets.hit(&ray))
+                .map(|hit| hit.id);
+            if looking_at != self.looking_at {
+                match looking_at {
+                    Some(id) => println!("looking at target {}", id),
+                    None => println!("looking away"),
+                }
+                self.looking_at = looking_at;
             }
-
-        let cmd = cmds[frame];
-        unsafe {
-            vk_device.begin_command_buffer(cmd, &vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)).unwrap();
-            vk_device.cmd_begin_render_pass(
-                cmd,
-                &vk::RenderPassBeginInfo::default()
-                    .render_pass(render_pass)
-                    .framebuffer(swapchain.buffers[image_index as usize].framebuffer)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D::default(),
-                        extent: swapchain.resolution,
-                    })
-                    .clear_values(&[vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    }]),
-                vk::SubpassContents::INLINE,
-            );
         }
 
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: swapchain.resolution.width as f32,
-            height: swapchain.resolution.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }];
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain.resolution,
-        }];
-
-        unsafe {
-            vk_device.cmd_set_viewport(cmd, 0, &viewports);
-            vk_device.cmd_set_scissor(cmd, 0, &scissors);
-
-            vk_device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
-            vk_device.cmd_draw(cmd, 3, 1, 0, 0);
-
-            vk_device.cmd_end_render_pass(cmd);
-            vk_device.end_command_buffer(cmd).unwrap();
+        for (hand, gestures) in Hand::BOTH.into_iter().zip(&mut self.gestures) {
+            let joints = hands.locate(hand, frame.space, frame.predicted_display_time)?;
+            for event in gestures.update(joints.as_ref()) {
+                println!("{:?} hand: {:?}", hand, event);
+            }
         }
 
-        session.sync_actions(&[(&action_set).into()]).unwrap();
-
-        let right_location = right_space.locate(&stage, xr_frame_state.predicted_display_time).unwrap();
-        let left_location = left_space.locate(&stage, xr_frame_state.predicted_display_time).unwrap();
-
         let mut printed = false;
-        if left_action.is_active(&session, xr::Path::NULL).unwrap() {
-            print!(
-                "Left Hand: ({:0<12},{:0<12},{:0<12}), ",
-                left_location.pose.position.x,
-                left_location.pose.position.y,
-                left_location.pose.position.z
-            );
-            printed = true;
-        }
-
-        if right_action.is_active(&session, xr::Path::NULL).unwrap() {
+        for hand in Hand::BOTH {
+            let state = snapshot.hand(hand);
+            if state.menu.current_state && state.menu.changed_since_last_sync {
+                self.recenter = true;
+            }
+            if !state.select.is_active {
+                continue;
+            }
+            let pose = hands.pose(hand, frame.space, frame.predicted_display_time, state)?;
+            let Some(position) = pose.palm().map(|palm| palm.position) else {
+                continue;
+            };
             print!(
-                "Right Hand: ({:0<12},{:0<12},{:0<12})",
-                right_location.pose.position.x,
-                right_location.pose.position.y,
-                right_location.pose.position.z
+                "{:?} Hand: ({:0<12},{:0<12},{:0<12}) ",
+                hand, position.x, position.y, position.z
             );
+            if state.select_pressed() {
+                print!("select ");
+                haptics.play(hand, HapticPattern::double_click());
+            }
             printed = true;
         }
         if printed {
             println!();
         }
 
-        let (_, views) = session.locate_views(VIEW_TYPE, xr_frame_state.predicted_display_time, &stage).unwrap();
-        swapchain.handle.wait_image(xr::Duration::INFINITE).unwrap();
-
-        unsafe {
-            vk_device.queue_submit(queue, &[vk::SubmitInfo::default().command_buffers(&[cmd])], fences[frame]).unwrap();
+        if let Some(boundary) = &mut self.boundary {
+            let grips = Hand::BOTH
+                .into_iter()
+                .map(|hand| snapshot.hand(hand).grip)
+                .filter(|grip| {
+                    grip.location_flags
+                        .contains(xr::SpaceLocationFlags::POSITION_VALID)
+                })
+                .map(|grip| grip.pose.position);
+            let tracked: Vec<_> = head.into_iter().chain(grips).collect();
+            boundary.update(frame.play_area.as_ref(), &tracked);
         }
-        swapchain.handle.release_image().unwrap();
+        haptics.update(frame.session, &input.haptic, frame.predicted_display_time)
+    }
 
-        let rect = xr::Rect2Di {
-            offset: xr::Offset2Di { x: 0, y: 0 },
-            extent: xr::Extent2Di {
-                width: swapchain.resolution.width as _,
-                height: swapchain.resolution.height as _,
-            },
+    fn record(&mut self, render: &mut RenderContext, view_count: u32) -> Result<()> {
+        let Some(pipeline) = &self.pipeline else {
+            return Ok(());
         };
+        let vk_device = &render.device.device;
+        unsafe {
+            vk_device.cmd_bind_pipeline(
+                render.cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.handle,
+            );
+            // Without multiview the shader can't see gl_ViewIndex, so it's passed in instead.
+            if render.config.view_mode == ViewMode::PerView {
+                vk_device.cmd_push_constants(
+                    render.cmd,
+                    pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    &render.view.to_ne_bytes(),
+                );
+            }
+            vk_device.cmd_draw(render.cmd, 3, 1, 0, 0);
+        }
+        match &mut self.boundary {
+            Some(boundary) => boundary.record(render, view_count),
+            None => Ok(()),
+        }
+    }
 
-        frame_stream.end(xr_frame_state.predicted_display_time, environment_blend_mode, &[&xr::CompositionLayerProjection::new().space(&stage).views(&[xr::CompositionLayerProjectionView::new().pose(views[0].pose).fov(views[0].fov).sub_image(xr::SwapchainSubImage::new().swapchain(&swapchain.handle).image_array_index(0).image_rect(rect)), xr::CompositionLayerProjectionView::new().pose(views[1].pose).fov(views[1].fov).sub_image(xr::SwapchainSubImage::new().swapchain(&swapchain.handle).image_array_index(1).image_rect(rect))])]).unwrap();
-        frame = (frame + 1) % PIPELINE_DEPTH as usize;
+    fn shutdown(&mut self, _ctx: &KaleidoContext) {
+        // Everything holding on to the session goes before the context tears it down.
+        self.input = None;
+        self.gaze = None;
+        self.hands = None;
+        self.haptics = None;
+        self.boundary = None;
+        self.pipeline = None;
     }
 
-    unsafe {
-        drop((session, frame_wait, frame_stream, stage, action_set, left_space, right_space, left_action, right_action));
-        vk_device.wait_for_fences(&fences, true, !0).unwrap();
-        for fence in fences {
-            vk_device.destroy_fence(fence, None);
-        }
+    fn recenter_requested(&mut self) -> bool {
+        std::mem::take(&mut self.recenter)
+    }
 
-        if let Some(swapchain) = swapchain {
-            for buffer in swapchain.buffers {
-                vk_device.destroy_framebuffer(buffer.framebuffer, None);
-                vk_device.destroy_image_view(buffer.color, None);
-            }
-        }
+    fn reference_space_changed(&mut self, change: &SpaceChange) {
+        println!("{:?} space recentered", change.ty);
+    }
 
-        vk_device.destroy_pipeline(pipeline, None);
-        vk_device.destroy_pipeline_layout(pipeline_layout, None);
-        vk_device.destroy_command_pool(cmd_pool, None);
-        vk_device.destroy_render_pass(render_pass, None);
-        vk_device.destroy_device(None);
-        vk_instance.destroy_instance(None);
+    fn exit_requested(&self) -> bool {
+        !self.running.load(Ordering::Relaxed)
     }
 
-    println!("exiting cleanly");
+    fn resolution_controller(&self) -> Option<ResolutionController> {
+        Some(ResolutionController::default())
+    }
 }
-
@@ -0,0 +1,58 @@
+//! Kaleido wraps the OpenXR + Vulkan setup from the openxrs multiview example behind a small set
+//! of reusable building blocks.
+//!
+//! * [`kapp`] holds the [`App`] trait and [`run`], which drives an app through the frame loop.
+//! * [`kabstract`] holds the initialization and resource creation functions.
+//! * [`kstructs`] holds the types those functions hand back.
+//! * [`kevents`] holds [`kevents::EventBus`], which routes OpenXR events to typed handlers.
+//! * [`kinput`] holds action sets with per-hand actions and the standard controller input.
+//! * [`khands`] holds [`khands::HandTracking`], which locates every joint of both hands through
+//!   XR_EXT_hand_tracking and falls back to the controllers without it.
+//! * [`keyegaze`] holds [`keyegaze::EyeGaze`], which turns XR_EXT_eye_gaze_interaction into a
+//!   gaze ray, and [`keyegaze::GazeTargets`] to test it against.
+//! * [`kgestures`] holds [`kgestures::GestureRecognizer`], which turns hand joints into pinch,
+//!   grab, point, thumbs-up and open-palm gestures.
+//! * [`khaptics`] holds controller vibration and [`khaptics::HapticSequencer`], which plays
+//!   multi-pulse patterns from the frame loop.
+//! * [`kmanifest`] holds [`kmanifest::ActionManifest`], which loads actions and their bindings
+//!   from a JSON file and validates them.
+//! * [`kbindings`] holds binding tables for common controllers and suggests an app's bindings
+//!   for all of them.
+//! * [`klifecycle`] holds [`klifecycle::SessionLifecycle`], which tracks the session state and
+//!   runs callbacks as the session becomes visible, gains or loses focus, or stops.
+//! * [`kspace`] holds [`kspace::ReferenceSpace`], which picks the reference space to run in from
+//!   an ordered list, applies an offset and recenters it, and [`kspace::PlayArea`], the bounds
+//!   of the space the user can move in.
+//! * [`kboundary`] holds [`kboundary::BoundaryGrid`], which draws the play area's edges as a
+//!   grid that fades in as the user gets close to them.
+//! * [`kcontext`] holds [`KaleidoContext`] and the builder that brings OpenXR and Vulkan up.
+//! * [`kconstants`] holds the formats and view configuration the renderer is built around.
+//! * [`kresolution`] holds [`kresolution::ResolutionController`], which scales the render
+//!   resolution to hold the display rate.
+//! * [`kerror`] holds [`KaleidoError`], which every fallible kaleido function returns.
+//!
+//! See the `kvulkan` example for an app built on [`run`].
+
+pub mod kabstract;
+pub mod kapp;
+pub mod kbindings;
+pub mod kboundary;
+pub mod kconstants;
+pub mod kcontext;
+pub mod kerror;
+pub mod kevents;
+pub mod keyegaze;
+pub mod kgestures;
+pub mod khands;
+pub mod khaptics;
+pub mod kinput;
+pub mod klifecycle;
+pub mod kmanifest;
+mod kmath;
+pub mod kresolution;
+pub mod kspace;
+pub mod kstructs;
+
+pub use kapp::{run, App};
+pub use kcontext::KaleidoContext;
+pub use kerror::KaleidoError;